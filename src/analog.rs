@@ -0,0 +1,214 @@
+use crate::{ADSREvent, ADSRParamKind, ADSRPhase};
+#[cfg(feature = "dasp")]
+use dasp_signal::Signal;
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)] // std's own float methods take over when tests link std
+use num_traits::Float;
+
+// envelope modeled on an RC circuit: every stage charges toward a target that overshoots
+// the level it stops at, so each sample is a single multiply-add instead of a powf.
+// like the hardware, stage times are full scale: decay and release take their whole time
+// only when they travel from 1.0 all the way down to 0.0
+pub struct AnalogADSR {
+    attack_time: f32,
+    decay_time: f32,
+    sustain_level: f32,
+    release_time: f32,
+    attack_ratio: f32,        // how far past 1.0 the attack aims, smaller is more curved
+    decay_release_ratio: f32, // how far below the goal decay and release aim
+    attack_coef: f32,
+    attack_base: f32,
+    decay_coef: f32,
+    decay_base: f32,
+    release_coef: f32,
+    release_base: f32,
+    current_event: ADSREvent,
+    current_phase: ADSRPhase,
+    current_val: f32,
+    next_event: ADSREvent,
+    sample_rate: f32,
+}
+
+impl AnalogADSR {
+    pub fn new(a: f32, d: f32, s: f32, r: f32, sample_rate: f32) -> Self {
+        assert!(ADSRParamKind::AttackTime(a).is_valid());
+        assert!(ADSRParamKind::DecayTime(d).is_valid());
+        assert!(ADSRParamKind::SustainLevel(s).is_valid());
+        assert!(ADSRParamKind::ReleaseTime(r).is_valid());
+        let mut adsr = AnalogADSR {
+            attack_time: a,
+            decay_time: d,
+            sustain_level: s,
+            release_time: r,
+            attack_ratio: 0.3,
+            decay_release_ratio: 0.0001,
+            attack_coef: 0.0,
+            attack_base: 0.0,
+            decay_coef: 0.0,
+            decay_base: 0.0,
+            release_coef: 0.0,
+            release_base: 0.0,
+            current_event: ADSREvent::NoteOff,
+            current_phase: ADSRPhase::Silence,
+            current_val: 0.0,
+            next_event: ADSREvent::NoteOff,
+            sample_rate,
+        };
+        adsr.update_coefs();
+        adsr
+    }
+
+    // only the time and sustain kinds have an analog counterpart, other kinds are ignored
+    pub fn set_param(&mut self, param: ADSRParamKind) {
+        assert!(param.is_valid());
+        match param {
+            ADSRParamKind::AttackTime(t) => {
+                self.attack_time = t;
+            },
+            ADSRParamKind::DecayTime(t) => {
+                self.decay_time = t;
+            },
+            ADSRParamKind::SustainLevel(l) => {
+                self.sustain_level = l;
+            },
+            ADSRParamKind::ReleaseTime(t) => {
+                self.release_time = t;
+            },
+            _ => {}
+        }
+        self.update_coefs();
+    }
+
+    pub fn set_ratios(&mut self, attack_ratio: f32, decay_release_ratio: f32) {
+        assert!(attack_ratio > 0.0);
+        assert!(decay_release_ratio > 0.0);
+        self.attack_ratio = attack_ratio;
+        self.decay_release_ratio = decay_release_ratio;
+        self.update_coefs();
+    }
+
+    // velocity is not modeled, every note on is full velocity
+    pub fn set_next_event(&mut self, event: ADSREvent) {
+        self.next_event = match event {
+            ADSREvent::NoteOnWithVelocity(_) => ADSREvent::NoteOn,
+            _ => event,
+        };
+    }
+
+    pub fn generate(&mut self) -> f32 {
+        if self.next_event != self.current_event {
+            self.current_phase = match self.next_event {
+                ADSREvent::NoteOn | ADSREvent::NoteOnWithVelocity(_) | ADSREvent::Retrigger => ADSRPhase::Attack,
+                ADSREvent::NoteOff if self.current_phase == ADSRPhase::Silence => ADSRPhase::Silence,
+                ADSREvent::NoteOff => ADSRPhase::Release,
+            };
+            if self.next_event == ADSREvent::Retrigger {
+                // charge again from wherever the capacitor is, then held like a note on
+                self.next_event = ADSREvent::NoteOn;
+            }
+            self.current_event = self.next_event;
+        }
+
+        match self.current_phase {
+            ADSRPhase::Attack => {
+                self.current_val = self.attack_base + self.current_val * self.attack_coef;
+                if self.current_val >= 1.0 {
+                    self.current_val = 1.0;
+                    self.current_phase = ADSRPhase::Decay;
+                }
+            },
+            ADSRPhase::Decay => {
+                self.current_val = self.decay_base + self.current_val * self.decay_coef;
+                if self.current_val <= self.sustain_level {
+                    self.current_val = self.sustain_level;
+                    self.current_phase = ADSRPhase::Sustain;
+                }
+            },
+            ADSRPhase::Sustain => {
+                self.current_val = self.sustain_level;
+            },
+            ADSRPhase::Release => {
+                self.current_val = self.release_base + self.current_val * self.release_coef;
+                if self.current_val <= 0.0 {
+                    self.current_val = 0.0;
+                    self.current_phase = ADSRPhase::Silence;
+                }
+            },
+            _ => {
+                self.current_val = 0.0;
+            }
+        }
+        self.current_val
+    }
+
+    // one-pole coefficient reaching 1/(1+ratio) of the way in `time` seconds
+    fn coef(&self, time: f32, ratio: f32) -> f32 {
+        let samples = time * self.sample_rate;
+        if samples <= 0.0 {
+            0.0
+        } else {
+            (-((1.0 + ratio) / ratio).ln() / samples).exp()
+        }
+    }
+
+    fn update_coefs(&mut self) {
+        self.attack_coef = self.coef(self.attack_time, self.attack_ratio);
+        self.attack_base = (1.0 + self.attack_ratio) * (1.0 - self.attack_coef);
+        self.decay_coef = self.coef(self.decay_time, self.decay_release_ratio);
+        self.decay_base = (self.sustain_level - self.decay_release_ratio) * (1.0 - self.decay_coef);
+        self.release_coef = self.coef(self.release_time, self.decay_release_ratio);
+        self.release_base = -self.decay_release_ratio * (1.0 - self.release_coef);
+    }
+}
+
+#[cfg(feature = "dasp")]
+impl Signal for AnalogADSR {
+    type Frame = f32;
+
+    fn next(&mut self) -> Self::Frame {
+        self.generate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples_in(adsr: &mut AnalogADSR, phase: ADSRPhase) -> usize {
+        let mut n = 0;
+        while adsr.current_phase == phase || n == 0 {
+            adsr.generate();
+            n += 1;
+        }
+        n
+    }
+
+    #[test]
+    fn stage_durations() {
+        let mut adsr = AnalogADSR::new(0.1, 0.2, 0.5, 0.3, 1000.0);
+        adsr.set_next_event(ADSREvent::NoteOn);
+        assert!((99..=101).contains(&samples_in(&mut adsr, ADSRPhase::Attack)));
+        assert!(samples_in(&mut adsr, ADSRPhase::Decay) <= 201);
+        assert!(adsr.current_phase == ADSRPhase::Sustain && adsr.current_val == 0.5);
+        adsr.set_next_event(ADSREvent::NoteOff);
+        assert!(samples_in(&mut adsr, ADSRPhase::Release) <= 301);
+        assert!(adsr.current_phase == ADSRPhase::Silence && adsr.current_val == 0.0);
+    }
+
+    #[test]
+    fn retrigger_on_next_sample() {
+        let mut adsr = AnalogADSR::new(0.1, 0.2, 0.5, 0.3, 1000.0);
+        adsr.set_next_event(ADSREvent::NoteOn);
+        for _ in 0..400 {
+            adsr.generate();
+        }
+        for _ in 0..2 {
+            adsr.set_next_event(ADSREvent::Retrigger);
+            assert!(adsr.current_phase == ADSRPhase::Sustain && adsr.current_val == 0.5);
+            assert!(adsr.generate() > 0.5 && adsr.current_phase == ADSRPhase::Attack);
+            for _ in 0..400 {
+                adsr.generate();
+            }
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{ADSRParamKind, ADSRParams, ParamConstructor, ADSR};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+const KINDS: [ParamConstructor; 15] = [
+    ADSRParamKind::DelayTime,
+    ADSRParamKind::AttackTime,
+    ADSRParamKind::HoldTime,
+    ADSRParamKind::DecayTime,
+    ADSRParamKind::SustainLevel,
+    ADSRParamKind::ReleaseTime,
+    ADSRParamKind::AttackCurve,
+    ADSRParamKind::DecayCurve,
+    ADSRParamKind::ReleaseCurve,
+    ADSRParamKind::LoopStart,
+    ADSRParamKind::PeakLevel,
+    ADSRParamKind::StartLevel,
+    ADSRParamKind::VelocityToPeak,
+    ADSRParamKind::VelocityToSustain,
+    ADSRParamKind::KeyTracking,
+];
+
+// numeric parameters as f32 bits in atomics, shared (e.g. in an Arc) between a UI thread that
+// writes whenever it likes and an audio thread that applies them once per block. neither side
+// ever waits. shapes are not covered and are set on the envelope directly
+pub struct AtomicAdsrParams {
+    values: [AtomicU32; KINDS.len()], // NaN for curve factors of stages with another shape
+}
+
+impl AtomicAdsrParams {
+    pub fn new(params: &ADSRParams) -> Self {
+        AtomicAdsrParams {
+            values: core::array::from_fn(|i| {
+                let value = params.numeric(&KINDS[i](0.0)).map_or(f32::NAN, |(current, _, _)| current);
+                AtomicU32::new(value.to_bits())
+            }),
+        }
+    }
+
+    // from any thread
+    pub fn set(&self, param: ADSRParamKind) {
+        assert!(param.is_valid());
+        let kind = core::mem::discriminant(&param);
+        let index = KINDS.iter().position(|make| core::mem::discriminant(&make(0.0)) == kind);
+        let value = param.numeric_value();
+        assert!(index.is_some() && value.is_some()); // shapes are not numeric
+        self.values[index.unwrap()].store(value.unwrap().to_bits(), Ordering::Relaxed);
+    }
+
+    // hand every value that differs from what the envelope has (or glides to) to its set_param(),
+    // usually once per block from the audio thread. stage times following the tempo are left alone
+    pub fn apply(&self, adsr: &mut ADSR) {
+        for (make, value) in KINDS.iter().zip(self.values.iter()) {
+            let value = f32::from_bits(value.load(Ordering::Relaxed));
+            if value.is_nan() {
+                continue;
+            }
+            let param = make(value);
+            if adsr.param_target(&param).is_some_and(|target| target != value) {
+                adsr.set_param(param);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))] // the parameters are shared across threads
+mod tests {
+    use super::*;
+    use crate::{ADSREvent, ADSRPhase};
+    use alloc::sync::Arc;
+
+    #[test]
+    fn cross_thread_params() {
+        let shared = Arc::new(AtomicAdsrParams::new(&ADSRParams::new(0.0, 0.0, 0.5, 0.1, 0.0, 0.0, 0.0)));
+        let mut adsr = ADSR::new(0.0, 0.0, 0.5, 0.1, 100.0);
+        adsr.set_stage_beats(ADSRPhase::Release, Some(1.0));
+        adsr.set_param_smoothing(0.02);
+        adsr.set_next_event(ADSREvent::NoteOn);
+        shared.apply(&mut adsr);
+        assert_eq!(adsr.generate(), 0.5);
+
+        let ui = Arc::clone(&shared);
+        std::thread::spawn(move || ui.set(ADSRParamKind::SustainLevel(0.9))).join().unwrap();
+        shared.apply(&mut adsr);
+        assert!((adsr.generate() - 0.7).abs() < 1e-6);
+        shared.apply(&mut adsr); // still gliding to the same value, nothing restarts
+        assert!((adsr.generate() - 0.9).abs() < 1e-6);
+
+        // the tempo synced release keeps its beat
+        adsr.set_next_event(ADSREvent::NoteOff);
+        shared.apply(&mut adsr);
+        assert_eq!(adsr.time_until_silence(), Some(50));
+    }
+}
@@ -0,0 +1,270 @@
+use crate::{to_f32, ADSREvent, ADSRParams, Cursor, Real, ADSR};
+use alloc::vec;
+use alloc::vec::Vec;
+
+// samples between looking for runs again while no voice has one
+const SCALAR_BLOCK: usize = 16;
+
+// a fixed set of envelopes played by note number. a note on takes a voice already playing
+// that note, else an idle one, else steals the oldest released voice, else the oldest held one.
+// every voice is a whole ADSR, so voice_mut() can set anything on one. only what mix_block()
+// steps inside runs is laid out one array per field, see Runs
+pub struct EnvelopeBank {
+    voices: Vec<ADSR>,
+    held: Vec<Option<u8>>, // note whose gate is open on each voice
+    started: Vec<u64>,     // note on count at the last note on of each voice
+    note_ons: u64,
+    runs: Runs,
+}
+
+// the state mix_block() steps for each voice inside a run, copied out of the voices at the start
+// of the run and handed back at its end, one array per field rather than one struct per voice,
+// so a sample of every voice is a few multiply-adds over contiguous memory. voices without a
+// run keep the neutral values, which add nothing to the mix
+struct Runs {
+    cursor: Vec<Option<Cursor>>, // run of each voice, None while it takes generate()
+    done: Vec<usize>,            // samples of the run stepped so far
+    base: Vec<Real>,
+    scale: Vec<Real>,
+    g: Vec<Real>,
+    mul: Vec<Real>,
+    add: Vec<Real>,
+    offset: Vec<f32>, // output stage
+    gain: Vec<f32>,
+    level: Vec<f32>, // of the last sample stepped
+}
+
+impl Runs {
+    fn new(voices: usize) -> Self {
+        Runs {
+            cursor: vec![None; voices],
+            done: vec![0; voices],
+            base: vec![0.0; voices],
+            scale: vec![0.0; voices],
+            g: vec![0.0; voices],
+            mul: vec![1.0; voices],
+            add: vec![0.0; voices],
+            offset: vec![0.0; voices],
+            gain: vec![0.0; voices],
+            level: vec![0.0; voices],
+        }
+    }
+
+    fn start(&mut self, v: usize, adsr: &ADSR, max: usize) {
+        let Some(run) = adsr.run(max) else {
+            return;
+        };
+        (self.base[v], self.scale[v], self.g[v], self.mul[v], self.add[v]) = (run.base, run.scale, run.g, run.mul, run.add);
+        (self.offset[v], self.gain[v]) = adsr.output_map();
+        self.cursor[v] = Some(run);
+        self.done[v] = 0;
+    }
+
+    // hand the samples stepped back to the voice
+    fn end(&mut self, v: usize, adsr: &mut ADSR) {
+        if let Some(run) = self.cursor[v].take() {
+            adsr.end_run(&run, self.done[v], self.level[v]);
+            (self.base[v], self.scale[v], self.g[v], self.mul[v], self.add[v]) = (0.0, 0.0, 0.0, 1.0, 0.0);
+            (self.offset[v], self.gain[v]) = (0.0, 0.0);
+        }
+    }
+}
+
+impl EnvelopeBank {
+    pub fn new(params: ADSRParams, voices: usize, sample_rate: f32) -> Self {
+        assert!(voices > 0);
+        EnvelopeBank {
+            voices: (0..voices).map(|_| ADSR::with_params(params.clone(), sample_rate)).collect(),
+            held: vec![None; voices],
+            started: vec![0; voices],
+            note_ons: 0,
+            runs: Runs::new(voices),
+        }
+    }
+
+    // velocity 0..1, returns the voice that plays the note
+    pub fn note_on(&mut self, note: u8, velocity: f32) -> usize {
+        let index = self.held.iter().position(|&held| held == Some(note))
+            .or_else(|| self.idle_voices().next())
+            .unwrap_or_else(|| {
+                (0..self.voices.len()).min_by_key(|&i| (self.held[i].is_some(), self.started[i])).unwrap()
+            });
+        let adsr = &mut self.voices[index];
+        let sounding = !adsr.is_finished();
+        adsr.set_key(note);
+        adsr.set_next_event(ADSREvent::NoteOnWithVelocity(velocity));
+        if sounding {
+            adsr.set_next_event(ADSREvent::Retrigger); // start over even if the gate never closed
+        }
+        self.note_ons += 1;
+        self.held[index] = Some(note);
+        self.started[index] = self.note_ons;
+        index
+    }
+
+    // releases the voice playing note, if any
+    pub fn note_off(&mut self, note: u8) {
+        if let Some(index) = self.held.iter().position(|&held| held == Some(note)) {
+            self.voices[index].set_next_event(ADSREvent::NoteOff);
+            self.held[index] = None;
+        }
+    }
+
+    pub fn set_sustain_pedal(&mut self, down: bool) {
+        for adsr in self.voices.iter_mut() {
+            adsr.set_sustain_pedal(down);
+        }
+    }
+
+    // voices that are silent with nothing to play
+    pub fn idle_voices(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.voices.len()).filter(|&i| self.held[i].is_none() && self.voices[i].is_finished())
+    }
+
+    pub fn len(&self) -> usize {
+        self.voices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.voices.is_empty()
+    }
+
+    pub fn voice(&self, index: usize) -> &ADSR {
+        &self.voices[index]
+    }
+
+    pub fn voice_mut(&mut self, index: usize) -> &mut ADSR {
+        &mut self.voices[index]
+    }
+
+    // one sample of every voice
+    pub fn generate(&mut self, out: &mut [f32]) {
+        assert_eq!(out.len(), self.voices.len());
+        for (sample, adsr) in out.iter_mut().zip(self.voices.iter_mut()) {
+            *sample = adsr.generate();
+        }
+    }
+
+    // one sample of all voices summed
+    pub fn mix(&mut self) -> f32 {
+        self.voices.iter_mut().map(|adsr| adsr.generate()).sum()
+    }
+
+    // consecutive samples of all voices summed, as mix() would give them up to rounding.
+    // voices inside a linear or Factor shaped stage are stepped together, the others (at stage
+    // boundaries and events, and in stages of other shapes) go through generate()
+    pub fn mix_block(&mut self, out: &mut [f32]) {
+        let n = self.voices.len();
+        let runs = &mut self.runs;
+        let mut pos = 0;
+        while pos < out.len() {
+            for (v, adsr) in self.voices.iter().enumerate() {
+                if runs.cursor[v].is_none() {
+                    runs.start(v, adsr, out.len() - pos);
+                }
+            }
+            // as far as every voice with a run can go, the others take generate() meanwhile
+            let len = (0..n).filter_map(|v| runs.cursor[v].map(|run| run.length - runs.done[v]))
+                .min().unwrap_or(SCALAR_BLOCK).min(out.len() - pos);
+
+            let (base, scale, mul, add) = (&runs.base[..n], &runs.scale[..n], &runs.mul[..n], &runs.add[..n]);
+            let (offset, gain) = (&runs.offset[..n], &runs.gain[..n]);
+            let (g, level) = (&mut runs.g[..n], &mut runs.level[..n]);
+            for sample in out[pos..pos + len].iter_mut() {
+                let mut sum = 0.0;
+                for v in 0..n {
+                    level[v] = to_f32(base[v] + scale[v] * g[v]);
+                    sum += offset[v] + gain[v] * level[v].clamp(0.0, 1.0);
+                    g[v] = g[v] * mul[v] + add[v];
+                }
+                for (adsr, run) in self.voices.iter_mut().zip(&runs.cursor) {
+                    if run.is_none() {
+                        sum += adsr.generate();
+                    }
+                }
+                *sample = sum;
+            }
+
+            for (v, adsr) in self.voices.iter_mut().enumerate() {
+                let Some(run) = runs.cursor[v] else {
+                    continue;
+                };
+                runs.done[v] += len;
+                if runs.done[v] == run.length {
+                    runs.end(v, adsr);
+                }
+            }
+            pos += len;
+        }
+        // events may come before the next block, so no run carries over
+        for (v, adsr) in self.voices.iter_mut().enumerate() {
+            runs.end(v, adsr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn voice_allocation() {
+        let mut params = ADSRParams::new(0.0, 0.0, 1.0, 0.02, 0.0, 0.0, 0.0);
+        params.set_param(crate::ADSRParamKind::VelocityToSustain(1.0));
+        let mut bank = EnvelopeBank::new(params, 2, 100.0);
+        let mut out = [0.0; 2];
+        assert_eq!(bank.idle_voices().count(), 2);
+        assert_eq!(bank.note_on(60, 1.0), 0);
+        assert_eq!(bank.note_on(64, 0.5), 1);
+        bank.generate(&mut out);
+        assert_eq!(out, [1.0, 0.5]);
+        assert_eq!(bank.idle_voices().count(), 0);
+
+        // the released voice goes first, even though it started earlier
+        bank.note_off(64);
+        assert_eq!(bank.note_on(67, 1.0), 1);
+        // then the oldest held one
+        assert_eq!(bank.note_on(72, 0.5), 0);
+        bank.generate(&mut out);
+        assert_eq!(out, [0.5, 1.0]);
+
+        bank.note_off(60); // no longer sounding
+        bank.note_off(67);
+        bank.note_off(72);
+        for _ in 0..3 {
+            bank.mix();
+        }
+        assert_eq!(bank.idle_voices().collect::<Vec<_>>(), vec![0, 1]);
+    }
+
+    #[test]
+    fn mix_block_matches_mix() {
+        let mut params = ADSRParams::new(0.05, 0.1, 0.4, 0.2, -0.3, 0.5, -0.7);
+        params.set_param(crate::ADSRParamKind::VelocityToPeak(0.5));
+        let mut bank = EnvelopeBank::new(params, 12, 1000.0);
+        bank.voice_mut(3).set_param(crate::ADSRParamKind::DecayShape(crate::CurveKind::Cosine)); // no fast path
+        bank.voice_mut(5).set_depth(0.5);
+        let mut scalar = EnvelopeBank::new(ADSRParams::new(0.05, 0.1, 0.4, 0.2, -0.3, 0.5, -0.7), 12, 1000.0);
+        for v in 0..12 {
+            *scalar.voice_mut(v) = bank.voice(v).clone();
+        }
+
+        let mut out = [0.0; 100];
+        for block in 0..10 {
+            for bank in [&mut bank, &mut scalar] {
+                bank.note_on(60 + block as u8, 0.1 * block as f32);
+                if block >= 3 {
+                    bank.note_off(57 + block as u8);
+                }
+            }
+            bank.mix_block(&mut out[..37 + block]);
+            for &sample in &out[..37 + block] {
+                assert!((sample - scalar.mix()).abs() < 1e-4);
+            }
+        }
+        for v in 0..12 {
+            assert_eq!(bank.voice(v).clock(), scalar.voice(v).clock());
+            assert!(bank.voice(v).current_phase() == scalar.voice(v).current_phase());
+        }
+    }
+}
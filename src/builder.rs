@@ -0,0 +1,153 @@
+use crate::{ADSRPhase, CurveKind, Segment, Stage};
+use alloc::vec::Vec;
+#[cfg(feature = "dasp")]
+use dasp_signal::Signal;
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)] // std's own float methods take over when tests link std
+use num_traits::Float;
+
+// builds a multi-segment envelope from breakpoints:
+// EnvelopeBuilder::new(sample_rate).point(0.0, 0.0).point(0.1, 1.0).curve(CurveKind::Cosine).point(0.5, 0.2).build()
+pub struct EnvelopeBuilder {
+    points: Vec<(f32, f32, CurveKind)>, // (seconds from start, level, curve of the segment ending here)
+    sample_rate: f32,
+}
+
+impl EnvelopeBuilder {
+    pub fn new(sample_rate: f32) -> Self {
+        assert!(sample_rate > 0.0);
+        EnvelopeBuilder {
+            points: Vec::new(),
+            sample_rate,
+        }
+    }
+
+    // times must not decrease, the envelope sits at the first level until the first point
+    pub fn point(mut self, time: f32, level: f32) -> Self {
+        assert!(time >= 0.0 && time.is_finite());
+        assert!(level.is_finite());
+        if let Some(&(last, _, _)) = self.points.last() {
+            assert!(time >= last);
+        }
+        self.points.push((time, level, CurveKind::Linear));
+        self
+    }
+
+    // shape of the segment ending at the last point
+    pub fn curve(mut self, curve: CurveKind) -> Self {
+        assert!(curve.is_valid());
+        let last = self.points.last_mut().expect("curve() needs a point before it");
+        last.2 = curve;
+        self
+    }
+
+    pub fn build(self) -> BreakpointEnvelope {
+        let mut segments = Vec::with_capacity(self.points.len());
+        let mut prev_level = self.points.first().map_or(0.0, |p| p.1);
+        let mut elapsed = 0;
+        for (time, level, curve) in self.points {
+            // round the end of every segment so lengths never drift from the breakpoints
+            let end = (time * self.sample_rate).round() as u64;
+            if end > elapsed {
+                segments.push(Breakpoint { from: prev_level, to: level, samples: end - elapsed, curve });
+                elapsed = end;
+            }
+            prev_level = level;
+        }
+        BreakpointEnvelope {
+            segments,
+            final_level: prev_level,
+            index: 0,
+            position: 0,
+        }
+    }
+}
+
+struct Breakpoint {
+    from: f32,
+    to: f32,
+    samples: u64,
+    curve: CurveKind,
+}
+
+impl Breakpoint {
+    fn stage(&self) -> Segment<'_> {
+        let phase = if self.to > self.from {
+            ADSRPhase::Attack
+        } else if self.to < self.from {
+            ADSRPhase::Decay
+        } else {
+            ADSRPhase::Hold
+        };
+        Segment::new(phase, self.from, self.to, self.samples as f32, &self.curve)
+    }
+}
+
+// plays its segments once from the start and then stays at the last level
+pub struct BreakpointEnvelope {
+    segments: Vec<Breakpoint>,
+    final_level: f32,
+    index: usize,  // current segment
+    position: u64, // samples into the current segment
+}
+
+impl BreakpointEnvelope {
+    pub fn restart(&mut self) {
+        self.index = 0;
+        self.position = 0;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.index >= self.segments.len()
+    }
+
+    pub fn phase(&self) -> ADSRPhase {
+        self.segments.get(self.index).map_or(ADSRPhase::Sustain, |s| s.stage().phase())
+    }
+
+    pub fn generate(&mut self) -> f32 {
+        let Some(segment) = self.segments.get(self.index) else {
+            return self.final_level;
+        };
+        // segments are measured in samples, so no per-sample division by the sample rate
+        let val = segment.stage().level(self.position as f32);
+        self.position += 1;
+        if self.position >= segment.samples {
+            self.index += 1;
+            self.position = 0;
+        }
+        val
+    }
+}
+
+#[cfg(feature = "dasp")]
+impl Signal for BreakpointEnvelope {
+    type Frame = f32;
+
+    fn next(&mut self) -> Self::Frame {
+        self.generate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breakpoints() {
+        let mut env = EnvelopeBuilder::new(10.0)
+            .point(0.0, 0.0)
+            .point(0.4, 1.0)
+            .point(0.6, 1.0)
+            .point(1.0, 0.2).curve(CurveKind::Cosine)
+            .build();
+        let out: Vec<f32> = (0..12).map(|_| env.generate()).collect();
+        assert_eq!(&out[..5], &[0.0, 0.25, 0.5, 0.75, 1.0]);
+        assert_eq!(out[6], 1.0);
+        assert!(out[7] < 1.0 && out[7] > out[8]);
+        assert!(env.is_finished());
+        assert_eq!(&out[10..], &[0.2, 0.2]);
+        env.restart();
+        assert_eq!(env.generate(), 0.0);
+    }
+}
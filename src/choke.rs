@@ -0,0 +1,79 @@
+use crate::{ADSREvent, ADSR};
+use alloc::vec::Vec;
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)] // std's own float methods take over when tests link std
+use num_traits::Float;
+
+// envelopes that silence each other, like open and closed hi-hats: a note on for one
+// member of a group kills the other members of that group with a short fade
+pub struct ChokeGroups {
+    voices: Vec<(ADSR, u32)>, // envelope and its group
+    fade_samples: u32,
+}
+
+impl ChokeGroups {
+    pub fn new(fade_time: f32, sample_rate: f32) -> Self {
+        assert!(fade_time >= 0.0);
+        ChokeGroups {
+            voices: Vec::new(),
+            fade_samples: (fade_time * sample_rate).round() as u32,
+        }
+    }
+
+    // returns the index used to address the envelope from now on
+    pub fn register(&mut self, adsr: ADSR, group: u32) -> usize {
+        self.voices.push((adsr, group));
+        self.voices.len() - 1
+    }
+
+    pub fn voice(&self, index: usize) -> &ADSR {
+        &self.voices[index].0
+    }
+
+    pub fn voice_mut(&mut self, index: usize) -> &mut ADSR {
+        &mut self.voices[index].0
+    }
+
+    pub fn set_next_event(&mut self, index: usize, event: ADSREvent) {
+        if event != ADSREvent::NoteOff {
+            let group = self.voices[index].1;
+            for (i, (adsr, g)) in self.voices.iter_mut().enumerate() {
+                if i != index && *g == group {
+                    adsr.kill(self.fade_samples);
+                }
+            }
+        }
+        self.voices[index].0.set_next_event(event);
+    }
+
+    // one sample of every envelope, in the order they were registered
+    pub fn generate(&mut self, out: &mut [f32]) {
+        assert_eq!(out.len(), self.voices.len());
+        for (sample, (adsr, _)) in out.iter_mut().zip(self.voices.iter_mut()) {
+            *sample = adsr.generate();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hi_hat_choke() {
+        let mut groups = ChokeGroups::new(0.02, 100.0);
+        let open = groups.register(ADSR::new(0.0, 0.0, 1.0, 1.0, 100.0), 1);
+        let closed = groups.register(ADSR::new(0.0, 0.0, 1.0, 1.0, 100.0), 1);
+        let other = groups.register(ADSR::new(0.0, 0.0, 1.0, 1.0, 100.0), 2);
+        let mut out = [0.0; 3];
+        groups.set_next_event(open, ADSREvent::NoteOn);
+        groups.set_next_event(other, ADSREvent::NoteOn);
+        groups.generate(&mut out);
+        assert_eq!(out, [1.0, 0.0, 1.0]);
+        groups.set_next_event(closed, ADSREvent::NoteOn);
+        groups.generate(&mut out);
+        assert_eq!(out, [0.5, 1.0, 1.0]);
+        groups.generate(&mut out);
+        assert_eq!(out, [0.0, 1.0, 1.0]);
+    }
+}
@@ -0,0 +1,33 @@
+use core::fmt;
+
+// why a parameter was rejected, naming it so a host can tell the user which knob is off
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ADSRError {
+    // NaN or infinite
+    NotFinite { param: &'static str, value: f32 },
+    // a number outside min..=max
+    OutOfRange { param: &'static str, value: f32, min: f32, max: f32 },
+    // a shape whose own arguments are out of range, see CurveKind::is_valid()
+    InvalidShape { param: &'static str },
+}
+
+impl fmt::Display for ADSRError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ADSRError::NotFinite { param, value } => {
+                write!(f, "{param} must be a finite number, got {value}")
+            },
+            ADSRError::OutOfRange { param, value, min, max } if max == f32::INFINITY => {
+                write!(f, "{param} must be at least {min}, got {value}")
+            },
+            ADSRError::OutOfRange { param, value, min, max } => {
+                write!(f, "{param} must be in {min}..={max}, got {value}")
+            },
+            ADSRError::InvalidShape { param } => {
+                write!(f, "{param} has arguments out of range")
+            },
+        }
+    }
+}
+
+impl core::error::Error for ADSRError {}
@@ -0,0 +1,55 @@
+// polynomial approximations behind set_fast_math(), good to a few parts in 10^5. both keep
+// their relative accuracy near zero (z near 0, x near 1), where the curves divide one small
+// value by another, so a curve factor close to 0 still comes out close to linear
+
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)] // std's own float methods take over when tests link std
+use num_traits::Float;
+
+// 2^z - 1
+pub(crate) fn exp2_m1(z: f32) -> f32 {
+    let z = z.clamp(-126.0, 127.0);
+    let i = z.round();
+    let f = z - i; // -0.5..0.5
+    let m1 = f * (0.693_136_7 + f * (0.240_225_3 + f * (0.055_838_28 + f * 0.009_656_71)));
+    let scale = f32::from_bits(((i as i32 + 127) as u32) << 23); // 2^i
+    scale * m1 + (scale - 1.0)
+}
+
+// log2(x) for positive normal x
+pub(crate) fn log2(x: f32) -> f32 {
+    let bits = x.to_bits();
+    let mut exponent = ((bits >> 23) & 0xff) as i32 - 127;
+    let mut m = f32::from_bits((bits & 0x007f_ffff) | 0x3f80_0000); // 1..2
+    if m > core::f32::consts::SQRT_2 {
+        m *= 0.5;
+        exponent += 1;
+    }
+    let u = m - 1.0; // -0.29..0.41
+    let p = 1.442_700_4 + u * (-0.721_195_75 + u * (0.479_925_57 + u * (-0.366_925_77 + u * (0.316_898_2 + u * -0.202_289_26))));
+    exponent as f32 + u * p
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accuracy() {
+        for i in 0..=2000 {
+            let x = 0.005 + i as f32 * 0.1;
+            assert!((log2(x) - x.log2()).abs() <= 2e-5 * x.log2().abs().max(1.0));
+            for j in 0..=20 {
+                let y = j as f32 * 0.1;
+                let want = x.powf(y) - 1.0; // as the curves use it
+                assert!((exp2_m1(y * log2(x)) - want).abs() <= 1e-4 * want.abs() + 1e-7);
+            }
+        }
+        for i in -1000..=1000 {
+            let z = i as f32 * 0.01;
+            assert!((exp2_m1(z) - z.exp2() + 1.0).abs() <= 1e-4 * (z.exp2() - 1.0).abs());
+        }
+        assert_eq!(exp2_m1(0.0), 0.0);
+        assert_eq!(log2(1.0), 0.0);
+    }
+}
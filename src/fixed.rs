@@ -0,0 +1,255 @@
+use crate::{flush_denormal, ADSREvent, ADSRError, ADSRParamKind, ADSRParams, ADSRPhase, Segment, Stage};
+#[cfg(feature = "dasp")]
+use dasp_signal::Signal;
+
+// an envelope with the sample rate fixed at compile time, for embedded and fixed-rate engines.
+// stage lengths are whole samples worked out when a parameter changes, and the time into a
+// stage is a sample count times the constant period, so nothing per sample depends on a rate
+// held at runtime. times turn into sample counts in const contexts too, e.g. for scheduling:
+// const GATE: u64 = AdsrFixed::<48000>::samples(0.25);
+// it plays delay, attack, hold, decay, sustain and release with the shapes, levels and velocity
+// response of ADSRParams. the modes, loops, glides and scheduling of ADSR take an ADSR
+pub struct AdsrFixed<const SR: u32> {
+    params: ADSRParams,
+    lengths: [u32; 5], // delay, attack, hold, decay and release in samples
+    stage: usize,      // into the note on timeline, 4 for sustain
+    position: u32,     // samples into the stage
+    release_from: f32,
+    level: f32,
+    velocity: f32,
+    next_velocity: f32,
+    current_event: ADSREvent,
+    next_event: ADSREvent,
+    current_phase: ADSRPhase,
+}
+
+impl<const SR: u32> AdsrFixed<SR> {
+    pub const SAMPLE_RATE: f32 = SR as f32;
+
+    // seconds per sample
+    pub const PERIOD: f32 = 1.0 / SR as f32;
+
+    pub fn new(a: f32, d: f32, s: f32, r: f32) -> Self {
+        Self::with_params(ADSRParams::new(a, d, s, r, 0.0, 0.0, 0.0))
+    }
+
+    pub fn with_params(params: ADSRParams) -> Self {
+        const { assert!(SR > 0) };
+        let mut adsr = AdsrFixed {
+            params,
+            lengths: [0; 5],
+            stage: 0,
+            position: 0,
+            release_from: 0.0,
+            level: 0.0,
+            velocity: 1.0,
+            next_velocity: 1.0,
+            current_event: ADSREvent::NoteOff,
+            next_event: ADSREvent::NoteOff,
+            current_phase: ADSRPhase::Silence,
+        };
+        adsr.update_lengths();
+        adsr
+    }
+
+    // seconds to the nearest number of samples
+    pub const fn samples(seconds: f32) -> u64 {
+        assert!(seconds >= 0.0);
+        (seconds * Self::SAMPLE_RATE + 0.5) as u64
+    }
+
+    pub fn params(&self) -> &ADSRParams {
+        &self.params
+    }
+
+    // panics on an invalid parameter, see try_set_param()
+    pub fn set_param(&mut self, param: ADSRParamKind) {
+        if let Err(error) = self.try_set_param(param) {
+            panic!("{error}");
+        }
+    }
+
+    // a stage already running keeps the samples it has played and takes the new length
+    pub fn try_set_param(&mut self, param: ADSRParamKind) -> Result<(), ADSRError> {
+        self.params.try_set_param(param)?;
+        self.update_lengths();
+        Ok(())
+    }
+
+    // NoteOn and Retrigger start the note from the start level, NoteOff releases it
+    pub fn set_next_event(&mut self, event: ADSREvent) {
+        match event {
+            ADSREvent::NoteOnWithVelocity(v) => {
+                assert!((0.0..=1.0).contains(&v));
+                self.next_velocity = v;
+                self.next_event = ADSREvent::NoteOn;
+            },
+            ADSREvent::NoteOn => {
+                self.next_velocity = 1.0;
+                self.next_event = ADSREvent::NoteOn;
+            },
+            ADSREvent::Retrigger => {
+                self.current_event = ADSREvent::NoteOff; // starts again on the next sample
+                self.next_event = ADSREvent::NoteOn;
+            },
+            ADSREvent::NoteOff => {
+                self.next_event = ADSREvent::NoteOff;
+            },
+        }
+    }
+
+    pub fn note_on(&mut self) {
+        self.set_next_event(ADSREvent::NoteOn);
+    }
+
+    pub fn note_off(&mut self) {
+        self.set_next_event(ADSREvent::NoteOff);
+    }
+
+    pub fn phase(&self) -> ADSRPhase {
+        self.current_phase
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.current_phase != ADSRPhase::Silence
+    }
+
+    // what the last generate() returned
+    pub fn value(&self) -> f32 {
+        self.level
+    }
+
+    pub fn generate(&mut self) -> f32 {
+        if self.next_event != self.current_event {
+            if self.next_event == ADSREvent::NoteOff {
+                if self.is_active() {
+                    self.release_from = self.level;
+                    self.current_phase = ADSRPhase::Release;
+                }
+            } else {
+                self.velocity = self.next_velocity;
+                self.stage = 0;
+            }
+            self.position = 0;
+            self.current_event = self.next_event;
+        }
+
+        let t = self.position as f32 * Self::PERIOD;
+        let level = if self.current_event == ADSREvent::NoteOff {
+            if self.position >= self.lengths[4] {
+                self.current_phase = ADSRPhase::Silence;
+                0.0
+            } else {
+                let release = Segment::new(ADSRPhase::Release, self.release_from, 0.0, self.seconds(4), self.params.release_shape());
+                flush_denormal(release.level(t))
+            }
+        } else {
+            while self.stage < 4 && self.position >= self.lengths[self.stage] {
+                self.position -= self.lengths[self.stage];
+                self.stage += 1;
+            }
+            let stage = self.note_on_stage();
+            let (phase, level) = (stage.phase(), stage.level(self.position as f32 * Self::PERIOD));
+            self.current_phase = phase;
+            level
+        };
+        self.position = self.position.saturating_add(1);
+        self.level = level.clamp(0.0, 1.0);
+        self.level
+    }
+
+    // the stage of the note on timeline being played
+    fn note_on_stage(&self) -> Segment<'_> {
+        let p = &self.params;
+        let scale = |amount: f32| 1.0 - amount * (1.0 - p.velocity_curve.apply(self.velocity));
+        let sustain = (p.sustain_level * scale(p.velocity_to_sustain)).min(1.0);
+        let peak = if p.decay_time > 0.0 || p.always_peak {
+            (p.peak_level * scale(p.velocity_to_peak)).min(1.0)
+        } else {
+            sustain
+        };
+        match self.stage {
+            0 => Segment::flat(ADSRPhase::Delay, 0.0, self.seconds(0)),
+            1 => Segment::new(ADSRPhase::Attack, p.start_level, peak, self.seconds(1), p.attack_shape()),
+            2 => Segment::flat(ADSRPhase::Hold, peak, self.seconds(2)),
+            3 => Segment::new(ADSRPhase::Decay, peak, sustain, self.seconds(3), p.decay_shape()),
+            _ => Segment::flat(ADSRPhase::Sustain, sustain, f32::INFINITY),
+        }
+    }
+
+    fn seconds(&self, stage: usize) -> f32 {
+        self.lengths[stage] as f32 * Self::PERIOD
+    }
+
+    fn update_lengths(&mut self) {
+        let p = &self.params;
+        let times = [p.delay_time, p.attack_time, p.hold_time, p.decay_time, p.release_time];
+        self.lengths = times.map(|t| Self::samples(t).min(u32::MAX as u64) as u32);
+    }
+}
+
+#[cfg(feature = "dasp")]
+impl<const SR: u32> Signal for AdsrFixed<SR> {
+    type Frame = f32;
+
+    fn next(&mut self) -> Self::Frame {
+        self.generate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CurveKind, ADSR};
+    use alloc::vec::Vec;
+
+    #[test]
+    fn same_as_runtime_rate() {
+        const GATE: u64 = AdsrFixed::<1000>::samples(0.25);
+        assert_eq!(GATE, 250);
+        let mut fixed = AdsrFixed::<1000>::new(0.1, 0.05, 0.5, 0.2);
+        let mut adsr = ADSR::new(0.1, 0.05, 0.5, 0.2, 1000.0);
+        for param in [
+            ADSRParamKind::DelayTime(0.02),
+            ADSRParamKind::HoldTime(0.01),
+            ADSRParamKind::AttackCurve(0.6),
+            ADSRParamKind::DecayShape(CurveKind::Cosine),
+            ADSRParamKind::VelocityToPeak(0.5),
+        ] {
+            fixed.set_param(param.clone());
+            adsr.set_param(param);
+        }
+        for n in 0..600 {
+            let event = match n {
+                0 => Some(ADSREvent::NoteOnWithVelocity(0.8)),
+                n if n == GATE => Some(ADSREvent::NoteOff),
+                _ => None,
+            };
+            if let Some(event) = event {
+                fixed.set_next_event(event);
+                adsr.set_next_event(event);
+            }
+            assert!((fixed.generate() - adsr.generate()).abs() < 1e-6, "sample {n}");
+        }
+        assert!(!fixed.is_active());
+    }
+
+    #[test]
+    fn whole_sample_stages() {
+        // 20 samples of delay, 100 of attack, 10 of hold and 50 of decay, to the sample
+        let mut fixed = AdsrFixed::<1000>::new(0.1, 0.05, 0.5, 0.2);
+        fixed.set_param(ADSRParamKind::DelayTime(0.02));
+        fixed.set_param(ADSRParamKind::HoldTime(0.01));
+        fixed.note_on();
+        let phases: Vec<ADSRPhase> = (0..200).map(|_| {
+            fixed.generate();
+            fixed.phase()
+        }).collect();
+        let first = |phase| phases.iter().position(|&p| p == phase).unwrap();
+        assert_eq!(first(ADSRPhase::Attack), 20);
+        assert_eq!(first(ADSRPhase::Hold), 120);
+        assert_eq!(first(ADSRPhase::Decay), 130);
+        assert_eq!(first(ADSRPhase::Sustain), 180);
+        assert_eq!(fixed.value(), 0.5);
+    }
+}
@@ -0,0 +1,297 @@
+use crate::{ADSREvent, ADSRParamKind, ADSRPhase};
+#[cfg(feature = "dasp")]
+use dasp_signal::Signal;
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)] // std's own float methods take over when tests link std
+use num_traits::Float;
+
+// full scale of a Q1.31 level
+pub const Q31_ONE: i32 = i32::MAX;
+
+// 1.0 in the Q31 math inside, one above Q31_ONE so that coefficients can reach it
+const UNITY: i64 = 1 << 31;
+
+// ln((1 + ratio) / ratio) in Q8.24 and ratio in Q31, for how far an exponential stage aims
+// past its end. the ratios of AnalogADSR: 0.3 for the attack, 0.0001 for decay and release
+const ATTACK_LOG: i64 = 24_601_054;
+const ATTACK_OVERSHOOT: i64 = 644_245_094;
+const DECAY_LOG: i64 = 154_525_547;
+const DECAY_OVERSHOOT: i64 = 214_748;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FixedCurve {
+    // a constant step worked out with one division when the stage starts
+    Linear,
+    // charges toward a target past the level it stops at, like AnalogADSR: a multiply and a
+    // shift per sample. the coefficient approximates 1 - e^-x by x / (1 + x/2), one division
+    Exponential,
+}
+
+// envelope in integer math for targets without an FPU, such as Cortex-M0 to M3: levels are
+// Q1.31, times are whole samples, and generating a sample takes no float operation. floats only
+// appear where they come in, in from_seconds(), set_param() and velocities
+pub struct FixedPointADSR {
+    attack: u32, // samples
+    decay: u32,
+    sustain: i64, // Q31
+    release: u32,
+    attack_curve: FixedCurve,
+    decay_release_curve: FixedCurve,
+    sample_rate: f32, // for set_param()
+    coef: i64,        // Q31 of the running exponential stage
+    step: i64,        // Q31 per sample of the running linear stage
+    level: i64,       // Q31
+    velocity: i64,    // Q31 of the sounding note
+    next_velocity: i64,
+    current_event: ADSREvent,
+    current_phase: ADSRPhase,
+    next_event: ADSREvent,
+}
+
+impl FixedPointADSR {
+    // times in samples, sustain in Q31
+    pub fn new(attack: u32, decay: u32, sustain: i32, release: u32) -> Self {
+        assert!(sustain >= 0);
+        FixedPointADSR {
+            attack,
+            decay,
+            sustain: sustain as i64,
+            release,
+            attack_curve: FixedCurve::Exponential,
+            decay_release_curve: FixedCurve::Exponential,
+            sample_rate: 0.0,
+            coef: 0,
+            step: 0,
+            level: 0,
+            velocity: UNITY,
+            next_velocity: UNITY,
+            current_event: ADSREvent::NoteOff,
+            current_phase: ADSRPhase::Silence,
+            next_event: ADSREvent::NoteOff,
+        }
+    }
+
+    // times in seconds and sustain as for ADSR, converted once here
+    pub fn from_seconds(a: f32, d: f32, s: f32, r: f32, sample_rate: f32) -> Self {
+        let mut adsr = Self::new(0, 0, 0, 0);
+        adsr.sample_rate = sample_rate;
+        for param in [
+            ADSRParamKind::AttackTime(a),
+            ADSRParamKind::DecayTime(d),
+            ADSRParamKind::SustainLevel(s),
+            ADSRParamKind::ReleaseTime(r),
+        ] {
+            adsr.set_param(param);
+        }
+        adsr
+    }
+
+    // only the time and sustain kinds have a fixed point counterpart, other kinds are ignored.
+    // times need the sample rate given to from_seconds()
+    pub fn set_param(&mut self, param: ADSRParamKind) {
+        assert!(param.is_valid());
+        let samples = |t: f32| (t * self.sample_rate).round() as u32;
+        match param {
+            ADSRParamKind::AttackTime(t) => self.attack = samples(t),
+            ADSRParamKind::DecayTime(t) => self.decay = samples(t),
+            ADSRParamKind::SustainLevel(l) => self.sustain = q31(l),
+            ADSRParamKind::ReleaseTime(t) => self.release = samples(t),
+            _ => {},
+        }
+    }
+
+    pub fn set_attack(&mut self, samples: u32) {
+        self.attack = samples;
+    }
+
+    pub fn set_decay(&mut self, samples: u32) {
+        self.decay = samples;
+    }
+
+    pub fn set_sustain(&mut self, level: i32) {
+        assert!(level >= 0);
+        self.sustain = level as i64;
+    }
+
+    pub fn set_release(&mut self, samples: u32) {
+        self.release = samples;
+    }
+
+    // take effect from the next stage on
+    pub fn set_curves(&mut self, attack: FixedCurve, decay_release: FixedCurve) {
+        self.attack_curve = attack;
+        self.decay_release_curve = decay_release;
+    }
+
+    // velocity scales the whole note, Retrigger starts the attack again from the current level
+    pub fn set_next_event(&mut self, event: ADSREvent) {
+        self.next_event = match event {
+            ADSREvent::NoteOnWithVelocity(v) => {
+                assert!((0.0..=1.0).contains(&v));
+                self.next_velocity = q31(v);
+                ADSREvent::NoteOn
+            },
+            ADSREvent::NoteOn => {
+                self.next_velocity = UNITY;
+                ADSREvent::NoteOn
+            },
+            ADSREvent::Retrigger | ADSREvent::NoteOff => event,
+        };
+    }
+
+    pub fn phase(&self) -> ADSRPhase {
+        self.current_phase
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.current_phase != ADSRPhase::Silence
+    }
+
+    // the next sample in Q1.31, 0 to Q31_ONE
+    pub fn generate(&mut self) -> i32 {
+        if self.next_event != self.current_event {
+            match self.next_event {
+                ADSREvent::NoteOff if self.current_phase == ADSRPhase::Silence => {},
+                ADSREvent::NoteOff => self.enter(ADSRPhase::Release),
+                _ => {
+                    self.velocity = self.next_velocity;
+                    self.enter(ADSRPhase::Attack);
+                },
+            }
+            if self.next_event == ADSREvent::Retrigger {
+                self.next_event = ADSREvent::NoteOn; // held like a note on from here
+            }
+            self.current_event = self.next_event;
+        }
+
+        let next = match self.current_phase {
+            ADSRPhase::Attack => Some((UNITY, ATTACK_OVERSHOOT, self.attack_curve, ADSRPhase::Decay)),
+            ADSRPhase::Decay => Some((self.sustain, DECAY_OVERSHOOT, self.decay_release_curve, ADSRPhase::Sustain)),
+            ADSRPhase::Release => Some((0, DECAY_OVERSHOOT, self.decay_release_curve, ADSRPhase::Silence)),
+            ADSRPhase::Sustain => {
+                self.level = self.sustain;
+                None
+            },
+            _ => {
+                self.level = 0;
+                None
+            },
+        };
+        if let Some((end, overshoot, curve, then)) = next {
+            let rising = end >= self.level;
+            self.level = match curve {
+                FixedCurve::Linear => self.level + self.step,
+                FixedCurve::Exponential => {
+                    let aim = if rising { end + overshoot } else { end - overshoot };
+                    self.level + (((aim - self.level) * self.coef) >> 31)
+                },
+            };
+            if (rising && self.level >= end) || (!rising && self.level <= end) {
+                self.level = end;
+                self.enter(then);
+            }
+        }
+        ((self.level * self.velocity) >> 31).min(Q31_ONE as i64) as i32
+    }
+
+    // the next sample in Q1.15
+    pub fn generate_q15(&mut self) -> i16 {
+        (self.generate() >> 16) as i16
+    }
+
+    // work out the step or coefficient of the stage entered, from the current level
+    fn enter(&mut self, phase: ADSRPhase) {
+        self.current_phase = phase;
+        let (samples, end, log, curve) = match phase {
+            ADSRPhase::Attack => (self.attack, UNITY, ATTACK_LOG, self.attack_curve),
+            ADSRPhase::Decay => (self.decay, self.sustain, DECAY_LOG, self.decay_release_curve),
+            ADSRPhase::Release => (self.release, 0, DECAY_LOG, self.decay_release_curve),
+            _ => return,
+        };
+        let samples = samples.max(1) as i64;
+        match curve {
+            FixedCurve::Linear => {
+                // rounded away from zero so the stage arrives on its last sample, not one later
+                let span = end - self.level;
+                self.step = (span + span.signum() * (samples - 1)) / samples;
+            },
+            FixedCurve::Exponential => {
+                self.coef = ((log << 31) / ((samples << 24) + log / 2)).min(UNITY);
+            },
+        }
+    }
+}
+
+fn q31(x: f32) -> i64 {
+    (x as f64 * UNITY as f64).round() as i64
+}
+
+#[cfg(feature = "dasp")]
+impl Signal for FixedPointADSR {
+    type Frame = i32;
+
+    fn next(&mut self) -> Self::Frame {
+        self.generate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AnalogADSR;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn linear_stages() {
+        let mut adsr = FixedPointADSR::new(4, 2, Q31_ONE / 2, 4);
+        adsr.set_curves(FixedCurve::Linear, FixedCurve::Linear);
+        adsr.set_next_event(ADSREvent::NoteOn);
+        let out: Vec<i32> = (0..8).map(|_| adsr.generate_q15() as i32).collect();
+        assert_eq!(out, vec![8192, 16384, 24576, 32767, 24575, 16383, 16383, 16383]);
+        adsr.set_next_event(ADSREvent::NoteOff);
+        let out: Vec<i32> = (0..5).map(|_| adsr.generate_q15() as i32).collect();
+        assert_eq!(out, vec![12287, 8191, 4095, 0, 0]);
+        assert!(adsr.phase() == ADSRPhase::Silence);
+    }
+
+    #[test]
+    fn retrigger_on_next_sample() {
+        let mut adsr = FixedPointADSR::new(4, 2, Q31_ONE / 2, 4);
+        adsr.set_curves(FixedCurve::Linear, FixedCurve::Linear);
+        adsr.set_next_event(ADSREvent::NoteOn);
+        for _ in 0..8 {
+            adsr.generate();
+        }
+        adsr.set_next_event(ADSREvent::NoteOnWithVelocity(0.5));
+        adsr.set_next_event(ADSREvent::Retrigger);
+        assert!(adsr.phase() == ADSRPhase::Sustain);
+        // from sustain a quarter of the way up in the first sample, at the new velocity
+        assert_eq!(adsr.generate_q15(), 10240);
+        assert!(adsr.phase() == ADSRPhase::Attack);
+        let out: Vec<i32> = (0..3).map(|_| adsr.generate_q15() as i32).collect();
+        assert_eq!(out, vec![12288, 14336, 16384]);
+    }
+
+    #[test]
+    fn follows_analog() {
+        let mut fixed = FixedPointADSR::from_seconds(0.05, 0.1, 0.4, 0.2, 1000.0);
+        let mut analog = AnalogADSR::new(0.05, 0.1, 0.4, 0.2, 1000.0);
+        fixed.set_next_event(ADSREvent::NoteOnWithVelocity(1.0));
+        analog.set_next_event(ADSREvent::NoteOn);
+        let (mut ours, mut theirs) = (Vec::new(), Vec::new());
+        for n in 0..600 {
+            if n == 300 {
+                fixed.set_next_event(ADSREvent::NoteOff);
+                analog.set_next_event(ADSREvent::NoteOff);
+            }
+            ours.push(fixed.generate() as f32 / Q31_ONE as f32);
+            theirs.push(analog.generate());
+        }
+        // the stages may turn a sample apart
+        for n in 1..599 {
+            assert!(theirs[n - 1..=n + 1].iter().any(|x| (ours[n] - x).abs() < 0.01), "sample {n}");
+        }
+        assert!(!fixed.is_active());
+    }
+}
@@ -0,0 +1,50 @@
+use crate::ADSR;
+use dasp_frame::Frame;
+use dasp_signal::Signal;
+use core::marker::PhantomData;
+
+// the envelope repeated in every channel of F, so it chains with multichannel signals:
+// stereo.mul_amp(adsr.frames::<[f32; 2]>())
+pub struct Frames<F> {
+    adsr: ADSR,
+    frame: PhantomData<F>,
+}
+
+impl ADSR {
+    pub fn frames<F: Frame<Sample = f32>>(self) -> Frames<F> {
+        Frames { adsr: self, frame: PhantomData }
+    }
+}
+
+impl<F> Frames<F> {
+    pub fn envelope_mut(&mut self) -> &mut ADSR {
+        &mut self.adsr
+    }
+
+    pub fn into_inner(self) -> ADSR {
+        self.adsr
+    }
+}
+
+impl<F: Frame<Sample = f32>> Signal for Frames<F> {
+    type Frame = F;
+
+    fn next(&mut self) -> Self::Frame {
+        let val = self.adsr.generate();
+        F::from_fn(|_| val)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stereo_frames() {
+        let mut adsr = ADSR::new(0.02, 0.0, 1.0, 0.0, 100.0);
+        adsr.note_on();
+        let stereo = dasp_signal::from_iter([[1.0_f32, -1.0]; 3]);
+        let out: Vec<[f32; 2]> = stereo.mul_amp(adsr.frames::<[f32; 2]>()).take(3).collect();
+        assert_eq!(out, vec![[0.0, 0.0], [0.5, -0.5], [1.0, -1.0]]);
+    }
+}
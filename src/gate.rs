@@ -0,0 +1,129 @@
+use crate::ADSREvent;
+#[cfg(feature = "dasp")]
+use crate::ADSR;
+#[cfg(feature = "dasp")]
+use dasp_signal::Signal;
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)] // std's own float methods take over when tests link std
+use num_traits::Float;
+
+// an envelope whose gate follows another signal, on while it reads 0.5 or more.
+// only changes of the gate are sent as events, so scheduled note offs and the like keep working
+#[cfg(feature = "dasp")]
+pub struct GatedBy<S> {
+    adsr: ADSR,
+    gate: S,
+    open: bool,
+}
+
+#[cfg(feature = "dasp")]
+impl ADSR {
+    pub fn gated_by<S: Signal<Frame = f32>>(self, gate: S) -> GatedBy<S> {
+        GatedBy { adsr: self, gate, open: false }
+    }
+}
+
+#[cfg(feature = "dasp")]
+impl<S> GatedBy<S> {
+    pub fn envelope_mut(&mut self) -> &mut ADSR {
+        &mut self.adsr
+    }
+
+    pub fn into_inner(self) -> (ADSR, S) {
+        (self.adsr, self.gate)
+    }
+}
+
+#[cfg(feature = "dasp")]
+impl<S: Signal<Frame = f32>> Signal for GatedBy<S> {
+    type Frame = f32;
+
+    fn next(&mut self) -> Self::Frame {
+        let open = self.gate.next() >= 0.5;
+        if open != self.open {
+            self.open = open;
+            self.adsr.gate(open);
+        }
+        self.adsr.generate()
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.gate.is_exhausted()
+    }
+}
+
+// turns audio into gate events: opens when the magnitude reaches threshold, closes once it
+// has stayed below threshold - hysteresis for hold_time seconds
+pub struct GateDetector {
+    threshold: f32,
+    hysteresis: f32,
+    hold_samples: u32,
+    open: bool,
+    held: u32, // samples left below the closing threshold before the gate closes
+}
+
+impl GateDetector {
+    pub fn new(threshold: f32, hysteresis: f32, hold_time: f32, sample_rate: f32) -> Self {
+        assert!(threshold > 0.0);
+        assert!((0.0..=threshold).contains(&hysteresis));
+        assert!(hold_time >= 0.0);
+        GateDetector {
+            threshold,
+            hysteresis,
+            hold_samples: (hold_time * sample_rate).round() as u32,
+            open: false,
+            held: 0,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    // feed one input sample, an event comes back when the gate changes
+    pub fn process(&mut self, input: f32) -> Option<ADSREvent> {
+        let level = input.abs();
+        if !self.open {
+            if level >= self.threshold {
+                self.open = true;
+                self.held = self.hold_samples;
+                return Some(ADSREvent::NoteOn);
+            }
+        } else if level >= self.threshold - self.hysteresis {
+            self.held = self.hold_samples;
+        } else if self.held > 0 {
+            self.held -= 1;
+        } else {
+            self.open = false;
+            return Some(ADSREvent::NoteOff);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    #[cfg(feature = "dasp")]
+    fn gate_signal() {
+        let gate = dasp_signal::from_iter([0.0, 1.0, 1.0, 0.7, 0.2, 0.0, 0.5]);
+        let adsr = ADSR::new(0.0, 0.0, 0.5, 0.0, 100.0);
+        let out: Vec<f32> = adsr.gated_by(gate).until_exhausted().collect();
+        assert_eq!(out, vec![0.0, 0.5, 0.5, 0.5, 0.0, 0.0, 0.5]);
+    }
+
+    #[test]
+    fn gate_detector() {
+        let mut detector = GateDetector::new(0.5, 0.2, 0.02, 100.0);
+        let input = [0.1, -0.6, 0.4, 0.1, 0.35, 0.1, 0.1, 0.1, 0.9];
+        let events: Vec<Option<ADSREvent>> = input.iter().map(|&x| detector.process(x)).collect();
+        let on = Some(ADSREvent::NoteOn);
+        let off = Some(ADSREvent::NoteOff);
+        assert!(events == vec![None, on, None, None, None, None, None, off, on]);
+        assert!(detector.is_open());
+    }
+}
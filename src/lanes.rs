@@ -0,0 +1,136 @@
+use crate::{to_f32, Real, ADSR};
+
+pub const LANES: usize = 8;
+
+// samples between looking for runs again while no lane has one
+const SCALAR_BLOCK: usize = 16;
+
+// LANES envelopes advanced together, a block at a time. while a voice stays inside a linear or
+// Factor shaped stage its curve is stepped with a multiply-add per sample instead of a powf, all
+// lanes at once in step_lanes(). samples at stage boundaries and events, and stages of other
+// shapes, go through generate() as usual
+pub struct EnvelopeLanes {
+    voices: [ADSR; LANES],
+}
+
+impl EnvelopeLanes {
+    pub fn new(voices: [ADSR; LANES]) -> Self {
+        EnvelopeLanes { voices }
+    }
+
+    pub fn voice(&self, lane: usize) -> &ADSR {
+        &self.voices[lane]
+    }
+
+    pub fn voice_mut(&mut self, lane: usize) -> &mut ADSR {
+        &mut self.voices[lane]
+    }
+
+    pub fn into_inner(self) -> [ADSR; LANES] {
+        self.voices
+    }
+
+    // one frame of all lanes per element of out
+    pub fn process_block(&mut self, out: &mut [[f32; LANES]]) {
+        let mut pos = 0;
+        while pos < out.len() {
+            let runs = core::array::from_fn::<_, LANES, _>(|l| self.voices[l].run(out.len() - pos));
+            // as far as every lane with a run can go, the others take generate() meanwhile
+            let len = runs.iter().flatten().map(|run| run.length).min().unwrap_or(SCALAR_BLOCK).min(out.len() - pos);
+
+            let mut base: [Real; LANES] = [0.0; LANES];
+            let mut scale: [Real; LANES] = [0.0; LANES];
+            let mut g: [Real; LANES] = [0.0; LANES];
+            let mut mul: [Real; LANES] = [1.0; LANES];
+            let mut add: [Real; LANES] = [0.0; LANES];
+            let mut offset = [0.0; LANES];
+            let mut gain = [0.0; LANES];
+            for (l, run) in runs.iter().enumerate() {
+                if let Some(run) = run {
+                    (base[l], scale[l], g[l], mul[l], add[l]) = (run.base, run.scale, run.g, run.mul, run.add);
+                    (offset[l], gain[l]) = self.voices[l].output_map();
+                }
+            }
+
+            let mut level: [Real; LANES] = [0.0; LANES];
+            let block = &mut out[pos..pos + len];
+            step_lanes(block, &Lanes { base, scale, mul, add, offset, gain }, &mut g, &mut level);
+            for (l, run) in runs.iter().enumerate() {
+                if run.is_none() {
+                    for frame in block.iter_mut() {
+                        frame[l] = self.voices[l].generate();
+                    }
+                }
+            }
+
+            for (l, run) in runs.iter().enumerate() {
+                if let Some(run) = run {
+                    self.voices[l].end_run(run, len, to_f32(level[l]));
+                }
+            }
+            pos += len;
+        }
+    }
+}
+
+// the fixed part of each lane's run, lanes without a run step the neutral values
+struct Lanes {
+    base: [Real; LANES],
+    scale: [Real; LANES],
+    mul: [Real; LANES],
+    add: [Real; LANES],
+    offset: [f32; LANES],
+    gain: [f32; LANES],
+}
+
+// every lane for every frame, without branches, so each frame is one vector step of all lanes.
+// checked in the assembly of a release build (cargo rustc --release -- --emit asm), where the
+// loop body is packed mulps/addps/minps/maxps on x86-64, two halves of four lanes with f32
+fn step_lanes(frames: &mut [[f32; LANES]], lanes: &Lanes, g: &mut [Real; LANES], level: &mut [Real; LANES]) {
+    for frame in frames.iter_mut() {
+        for l in 0..LANES {
+            level[l] = lanes.base[l] + lanes.scale[l] * g[l];
+            frame[l] = lanes.offset[l] + lanes.gain[l] * to_f32(level[l]).clamp(0.0, 1.0);
+            g[l] = g[l] * lanes.mul[l] + lanes.add[l];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ADSREvent, ADSRParamKind, EnvelopeMode};
+
+    #[test]
+    fn lanes_match_scalar() {
+        let voices: [ADSR; LANES] = core::array::from_fn(|l| {
+            let mut adsr = ADSR::new(0.05 * l as f32, 0.1, 0.1 * l as f32, 0.2, 1000.0);
+            adsr.set_param(ADSRParamKind::AttackCurve(0.1 * l as f32 - 0.3));
+            adsr.set_param(ADSRParamKind::DecayCurve(0.5));
+            adsr.set_param(ADSRParamKind::ReleaseCurve(-0.7));
+            adsr.schedule_event(3 * l as u64, ADSREvent::NoteOnWithVelocity(0.8));
+            adsr.schedule_event(300 + 7 * l as u64, ADSREvent::NoteOff);
+            adsr
+        });
+        let mut scalar = voices.clone();
+        scalar[2].set_mode(EnvelopeMode::Percussive);
+        scalar[5].set_param(ADSRParamKind::DecayShape(crate::CurveKind::Cosine)); // no fast path
+        scalar[6].set_sustain_pedal_level(0.5);
+        scalar[7].set_depth(0.5);
+        let mut lanes = EnvelopeLanes::new(scalar.clone());
+
+        let mut out = [[0.0; LANES]; 64];
+        for _ in 0..12 {
+            lanes.process_block(&mut out);
+            for frame in out.iter() {
+                for (l, adsr) in scalar.iter_mut().enumerate() {
+                    assert!((frame[l] - adsr.generate()).abs() < 1e-4);
+                }
+            }
+        }
+        for (l, adsr) in scalar.iter().enumerate() {
+            assert_eq!(lanes.voice(l).clock(), adsr.clock());
+            assert!(lanes.voice(l).current_phase() == adsr.current_phase());
+        }
+    }
+}
@@ -1,100 +1,683 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(any(feature = "std", feature = "libm")))]
+compile_error!("the float math needs either the \"std\" or the \"libm\" feature");
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "dasp")]
 use dasp_signal::Signal;
+use num_traits::ToPrimitive;
+#[cfg(feature = "std")]
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+
+mod analog;
+mod atomic;
+mod bank;
+mod builder;
+mod choke;
+mod error;
+mod fast_math;
+mod fixed;
+#[cfg(feature = "fixed-point")]
+mod fixed_point;
+#[cfg(feature = "dasp")]
+mod frame;
+mod gate;
+mod lanes;
+#[cfg(feature = "midi")]
+mod midi;
+mod queue;
+mod sf2;
+mod sfz;
+mod shared;
+mod stage;
+#[cfg(feature = "debug-validate")]
+mod validate;
+
+// precision of the timeline and curve math inside a voice, f64 with the "f64" feature for
+// drones and very slow envelopes where f32 steps would be audible. samples are f32 either way,
+// the *_as methods hand them and the curves over in any Float
+#[cfg(not(feature = "f64"))]
+pub(crate) type Real = f32;
+#[cfg(feature = "f64")]
+pub(crate) type Real = f64;
+
+const PI: Real = core::f64::consts::PI as Real;
+
+// back from the internal precision
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn to_f32(x: Real) -> f32 {
+    x as f32
+}
+
+// a constant or an f32 in the precision of T. floats convert to each other without failing
+pub(crate) fn cast<T: Float>(x: impl ToPrimitive) -> T {
+    T::from(x).unwrap()
+}
+
+// levels below this (-200 dB) are flushed to zero
+pub const FLUSH_LEVEL: f32 = 1e-10;
+
+// zero for levels closer to it than FLUSH_LEVEL, which keeps tails fed through filters or
+// feedback out of the denormal range that stalls x86 CPUs. the FTZ/DAZ flags of the FPU do the
+// same in hardware, but Rust assumes the default floating point environment and switching them
+// from safe code is not supported, so flush in the signal path instead
+pub fn flush_denormal(x: f32) -> f32 {
+    if x.abs() < FLUSH_LEVEL {
+        0.0
+    } else {
+        x
+    }
+}
+pub use analog::AnalogADSR;
+pub use atomic::AtomicAdsrParams;
+pub use bank::EnvelopeBank;
+pub use builder::{BreakpointEnvelope, EnvelopeBuilder};
+pub use choke::ChokeGroups;
+pub use error::ADSRError;
+pub use fixed::AdsrFixed;
+#[cfg(feature = "fixed-point")]
+pub use fixed_point::{FixedCurve, FixedPointADSR, Q31_ONE};
+#[cfg(feature = "dasp")]
+pub use frame::Frames;
+pub use gate::GateDetector;
+#[cfg(feature = "dasp")]
+pub use gate::GatedBy;
+pub use lanes::{EnvelopeLanes, LANES};
+#[cfg(feature = "midi")]
+pub use midi::{CcOutput, CcResolution};
+pub use num_traits::Float;
+pub use queue::{event_queue, EventConsumer, EventProducer};
+pub use shared::SharedParams;
+pub use stage::{locate, Segment, Stage};
+#[cfg(feature = "debug-validate")]
+pub use validate::{Invariant, Violation};
+use stage::locate_index;
 
 #[derive(Copy, Clone, PartialEq)]
 pub enum ADSREvent {
     NoteOn,
     NoteOff,
+    NoteOnWithVelocity(f32), // velocity 0..1, NoteOn is full velocity
+    Retrigger, // restart the note even while it is held, keeping its velocity
 }
 
 #[derive(Copy, Clone, PartialEq)]
 pub enum ADSRPhase {
+    Delay,
     Attack,
+    Hold,
     Decay,
     Sustain,
     Release,
     Silence,
+    Shutdown, // fast fade to silence after kill(), e.g. for voice stealing
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum EnvelopeMode {
+    Gated,   // attack/decay while the gate is on, sustain until note off, then release
+    OneShot, // note on fires attack then decay to zero regardless of gate length
+    Percussive, // like Gated but decays to zero while the gate is held, note off releases early
+    SustainLoop, // like Gated but loops from loop start to the end of decay while the gate is held
+    ASR, // attack to peak and stay there until note off, hold/decay/sustain level are ignored
+}
+
+// how often SustainLoop jumps back to loop start before settling at sustain
+#[derive(Copy, Clone, PartialEq)]
+pub enum LoopCount {
+    Infinite,
+    Times(u32), // the looped region plays n + 1 times
+}
+
+// what ADSR::set_param() does with a value out of range
+#[derive(Copy, Clone, PartialEq)]
+pub enum ParamPolicy {
+    Reject, // panic, try_set_param() reports it instead
+    Clamp,  // clamp into range, e.g. automation overshooting to 1.0001. NaN and invalid shapes are ignored
+}
+
+// what a note on does to an envelope that is still sounding, in release or restarted while held
+#[derive(Copy, Clone, PartialEq)]
+pub enum RetriggerMode {
+    HardReset,        // restart the attack from the start level
+    FromCurrentValue, // restart the attack from the current value, keeping the attack rate
+    Legato,           // keep the note on timeline where it stopped, gliding back from the current value
+}
+
+// what the stages move: levels are given as amplitudes either way, and the output is an amplitude
+#[derive(Copy, Clone, PartialEq)]
+pub enum LevelScale {
+    Linear,        // the amplitude
+    Decibels(f32), // the level in dB, from silence at minus this range (e.g. 96.0) up to 0 dB
 }
 
 #[derive(Copy, Clone, PartialEq)]
+pub enum Polarity {
+    Unipolar, // 0..1 * depth
+    Bipolar,  // -1..1 * depth
+}
+
+// shape of a stage, mapping normalized time 0..1 to normalized level 0..1
+// user-supplied shapes are expected to map 0..1 to 0..1 and are not validated, levels past
+// either end are clamped at the output
+#[derive(Clone)]
+pub enum CurveKind {
+    Factor(f32),      // exponential curve bent by a -1..1 factor, 0 is linear
+    SCurve(f32),      // two mirrored Factor curves meeting at (0.5, 0.5)
+    Bezier(f32, f32), // quadratic bezier through (0, 0) and (1, 1) with control point (x, y) in 0..1
+    Linear,                       // y = x
+    Exponential { tension: f32 }, // y = (e^(k*x) - 1) / (e^k - 1), k = tension >= 0, slow start
+    Logarithmic { tension: f32 }, // y = ln(1 + (e^k - 1) * x) / k, the inverse of Exponential, fast start
+    Cosine,                       // y = (1 - cos(pi * x)) / 2, eases in and out
+    Func(fn(f32) -> f32), // plain function, no allocation or dynamic dispatch (real-time safe)
+    Custom(Arc<dyn Fn(f32) -> f32 + Send + Sync>), // shared closure or trait object
+    Table(Arc<[f32]>), // levels at evenly spaced points from 0 to 1, interpolated linearly
+}
+
+impl CurveKind {
+    // wrap any closure, e.g. one capturing a lookup table
+    pub fn custom<F: Fn(f32) -> f32 + Send + Sync + 'static>(f: F) -> Self {
+        CurveKind::Custom(Arc::new(f))
+    }
+
+    // this shape sampled at size points, to be evaluated without any transcendental math
+    pub fn table(&self, size: usize) -> Self {
+        assert!(size >= 2);
+        let mut levels: Arc<[f32]> = Arc::from(vec![0.0; size]);
+        self.fill_table(Arc::get_mut(&mut levels).unwrap());
+        CurveKind::Table(levels)
+    }
+
+    // sample this shape at evenly spaced points from 0 to 1 into levels
+    fn fill_table(&self, levels: &mut [f32]) {
+        let last = (levels.len() - 1) as f32;
+        for (i, level) in levels.iter_mut().enumerate() {
+            *level = self.eval(i as f32 / last);
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            CurveKind::Factor(c) | CurveKind::SCurve(c) => {
+                (-1.0..=1.0).contains(&c)
+            },
+            CurveKind::Bezier(x, y) => {
+                (0.0..=1.0).contains(&x) && (0.0..=1.0).contains(&y)
+            },
+            CurveKind::Exponential { tension } | CurveKind::Logarithmic { tension } => {
+                tension >= 0.0 && tension.is_finite()
+            },
+            CurveKind::Linear | CurveKind::Cosine | CurveKind::Func(_) | CurveKind::Custom(_) => {
+                true
+            },
+            CurveKind::Table(ref levels) => {
+                levels.len() >= 2
+            },
+        }
+    }
+
+    pub fn eval(&self, x: f32) -> f32 {
+        to_f32(self.eval_real(x as Real, false))
+    }
+
+    // eval in the precision of T, e.g. f64 for analysis without f32 rounding. curves given as
+    // f32 functions or tables are evaluated in f32
+    pub fn eval_as<T: Float>(&self, x: T) -> T {
+        self.eval_real(x, false)
+    }
+
+    // fast approximates powf in the Factor and SCurve shapes
+    pub(crate) fn eval_real<T: Float>(&self, x: T, fast: bool) -> T {
+        let (one, two, half) = (T::one(), cast::<T>(2.0), cast::<T>(0.5));
+        match *self {
+            CurveKind::Factor(c) => {
+                ADSR::curve_function(x, one, one, cast(c), fast)
+            },
+            CurveKind::SCurve(c) => {
+                if x < half {
+                    half * ADSR::curve_function(two * x, one, one, cast(c), fast)
+                } else {
+                    one - half * ADSR::curve_function(two - two * x, one, one, cast(c), fast)
+                }
+            },
+            CurveKind::Bezier(cx, cy) => {
+                // solve x(u) = 2(1-u)u*cx + u^2 for u, then evaluate y(u)
+                let (cx, cy) = (cast::<T>(cx), cast::<T>(cy));
+                let a = one - two * cx;
+                let u = if a.abs() < cast(1e-6) {
+                    x
+                } else {
+                    (-cx + (cx * cx + a * x).sqrt()) / a
+                };
+                two * (one - u) * u * cy + u * u
+            },
+            CurveKind::Linear => {
+                x
+            },
+            CurveKind::Exponential { tension: k } => {
+                let k = cast::<T>(k);
+                if k == T::zero() {
+                    x
+                } else {
+                    (k * x).exp_m1() / k.exp_m1()
+                }
+            },
+            CurveKind::Logarithmic { tension: k } => {
+                let k = cast::<T>(k);
+                if k == T::zero() {
+                    x
+                } else {
+                    (k.exp_m1() * x).ln_1p() / k
+                }
+            },
+            CurveKind::Cosine => {
+                (one - (cast::<T>(PI) * x).cos()) / two
+            },
+            CurveKind::Func(f) => {
+                cast(f(cast(x)))
+            },
+            CurveKind::Custom(ref f) => {
+                cast(f(cast(x)))
+            },
+            CurveKind::Table(ref levels) => {
+                let pos = x.clamp(T::zero(), one) * cast(levels.len() - 1);
+                let i = pos.to_usize().unwrap_or(0).min(levels.len() - 2);
+                let (a, b) = (cast::<T>(levels[i]), cast::<T>(levels[i + 1]));
+                a + (b - a) * (pos - cast(i))
+            },
+        }
+    }
+}
+
+impl PartialEq for CurveKind {
+    // custom shapes compare by identity
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (CurveKind::Factor(a), CurveKind::Factor(b)) => a == b,
+            (CurveKind::SCurve(a), CurveKind::SCurve(b)) => a == b,
+            (CurveKind::Bezier(ax, ay), CurveKind::Bezier(bx, by)) => ax == bx && ay == by,
+            (CurveKind::Linear, CurveKind::Linear) => true,
+            (CurveKind::Exponential { tension: a }, CurveKind::Exponential { tension: b }) => a == b,
+            (CurveKind::Logarithmic { tension: a }, CurveKind::Logarithmic { tension: b }) => a == b,
+            (CurveKind::Cosine, CurveKind::Cosine) => true,
+            (CurveKind::Func(a), CurveKind::Func(b)) => core::ptr::fn_addr_eq(*a, *b),
+            (CurveKind::Custom(a), CurveKind::Custom(b)) => Arc::ptr_eq(a, b),
+            (CurveKind::Table(a), CurveKind::Table(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+// response of the level to note velocity, applied where VelocityToPeak and VelocityToSustain
+// scale the note, so one setting suits the feel of the keyboard
+#[derive(Clone)]
+pub enum VelocityCurve {
+    Linear,
+    Soft, // 1 - (1 - v)^2, louder for a light touch
+    Hard, // v^2, takes a heavier touch
+    Table(Arc<[f32]>), // levels 0..1 at evenly spaced velocities from 0 to 1, interpolated linearly.
+                       // 128 of them map MIDI velocities 0..=127 one to one
+}
+
+impl VelocityCurve {
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            VelocityCurve::Table(ref levels) => levels.len() >= 2 && levels.iter().all(|l| (0.0..=1.0).contains(l)),
+            _ => true,
+        }
+    }
+
+    // the level a velocity of 0..1 plays at
+    pub fn apply(&self, velocity: f32) -> f32 {
+        let v = velocity.clamp(0.0, 1.0);
+        match *self {
+            VelocityCurve::Linear => v,
+            VelocityCurve::Soft => 1.0 - (1.0 - v) * (1.0 - v),
+            VelocityCurve::Hard => v * v,
+            VelocityCurve::Table(ref levels) => {
+                let pos = v * (levels.len() - 1) as f32;
+                let i = (pos as usize).min(levels.len() - 2);
+                levels[i] + (levels[i + 1] - levels[i]) * (pos - i as f32)
+            },
+        }
+    }
+}
+
+impl PartialEq for VelocityCurve {
+    // tables compare by identity, as in CurveKind
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (VelocityCurve::Table(a), VelocityCurve::Table(b)) => Arc::ptr_eq(a, b),
+            _ => core::mem::discriminant(self) == core::mem::discriminant(other),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
 pub enum ADSRParamKind {
+    DelayTime(f32),
     AttackTime(f32),
+    HoldTime(f32),
     DecayTime(f32),
     SustainLevel(f32),
     ReleaseTime(f32),
     AttackCurve(f32),
     DecayCurve(f32),
     ReleaseCurve(f32),
+    LoopStart(f32), // seconds after the start of attack
+    PeakLevel(f32),
+    StartLevel(f32),
+    AttackShape(CurveKind),
+    DecayShape(CurveKind),
+    ReleaseShape(CurveKind),
+    VelocityToPeak(f32),    // 0..1, how much a soft note lowers the peak level
+    VelocityToSustain(f32), // 0..1, how much a soft note lowers the sustain level
+    KeyTracking(f32),       // 0..1, at 1.0 decay and release halve every octave above middle C
+    VelocityResponse(VelocityCurve),
 }
 
+pub(crate) type ParamConstructor = fn(f32) -> ADSRParamKind;
+
 impl ADSRParamKind {
-    pub fn is_valid(self) -> bool {
-        match self {
-            ADSRParamKind::AttackTime(t) => {
-                t >= 0.0
-            },
-            ADSRParamKind::DecayTime(t) => {
-                t >= 0.0
-            },
-            ADSRParamKind::SustainLevel(l) => {
-                l >= 0.0 && l <= 1.0
-            },
-            ADSRParamKind::ReleaseTime(t) => {
-                t >= 0.0
+    // the number carried, None for shapes
+    pub(crate) fn numeric_value(&self) -> Option<f32> {
+        match *self {
+            ADSRParamKind::AttackShape(_) | ADSRParamKind::DecayShape(_) | ADSRParamKind::ReleaseShape(_)
+            | ADSRParamKind::VelocityResponse(_) => None,
+            ADSRParamKind::DelayTime(v) | ADSRParamKind::AttackTime(v) | ADSRParamKind::HoldTime(v)
+            | ADSRParamKind::DecayTime(v) | ADSRParamKind::SustainLevel(v) | ADSRParamKind::ReleaseTime(v)
+            | ADSRParamKind::AttackCurve(v) | ADSRParamKind::DecayCurve(v) | ADSRParamKind::ReleaseCurve(v)
+            | ADSRParamKind::LoopStart(v) | ADSRParamKind::PeakLevel(v) | ADSRParamKind::StartLevel(v)
+            | ADSRParamKind::VelocityToPeak(v) | ADSRParamKind::VelocityToSustain(v) | ADSRParamKind::KeyTracking(v) => Some(v),
+        }
+    }
+
+    // kinds of numeric parameters, so at most this many glide at once
+    pub(crate) const NUMERIC: usize = 15;
+
+    // the closest valid parameter: numbers clamped into their range, None for NaN, for
+    // infinite times and for invalid shapes
+    pub(crate) fn clamped(self) -> Option<Self> {
+        if self.is_valid() {
+            return Some(self);
+        }
+        let value = self.numeric_value().filter(|v| !v.is_nan())?;
+        let (min, max) = self.range()?;
+        let make: ParamConstructor = match self {
+            ADSRParamKind::DelayTime(_) => ADSRParamKind::DelayTime,
+            ADSRParamKind::AttackTime(_) => ADSRParamKind::AttackTime,
+            ADSRParamKind::HoldTime(_) => ADSRParamKind::HoldTime,
+            ADSRParamKind::DecayTime(_) => ADSRParamKind::DecayTime,
+            ADSRParamKind::ReleaseTime(_) => ADSRParamKind::ReleaseTime,
+            ADSRParamKind::LoopStart(_) => ADSRParamKind::LoopStart,
+            ADSRParamKind::AttackCurve(_) => ADSRParamKind::AttackCurve,
+            ADSRParamKind::DecayCurve(_) => ADSRParamKind::DecayCurve,
+            ADSRParamKind::ReleaseCurve(_) => ADSRParamKind::ReleaseCurve,
+            ADSRParamKind::SustainLevel(_) => ADSRParamKind::SustainLevel,
+            ADSRParamKind::PeakLevel(_) => ADSRParamKind::PeakLevel,
+            ADSRParamKind::StartLevel(_) => ADSRParamKind::StartLevel,
+            ADSRParamKind::VelocityToPeak(_) => ADSRParamKind::VelocityToPeak,
+            ADSRParamKind::VelocityToSustain(_) => ADSRParamKind::VelocityToSustain,
+            ADSRParamKind::KeyTracking(_) => ADSRParamKind::KeyTracking,
+            ADSRParamKind::AttackShape(_) | ADSRParamKind::DecayShape(_) | ADSRParamKind::ReleaseShape(_)
+            | ADSRParamKind::VelocityResponse(_) => return None,
+        };
+        let value = value.clamp(min, max);
+        value.is_finite().then(|| make(value))
+    }
+
+    // name for error messages
+    pub(crate) fn name(&self) -> &'static str {
+        match *self {
+            ADSRParamKind::DelayTime(_) => "delay time",
+            ADSRParamKind::AttackTime(_) => "attack time",
+            ADSRParamKind::HoldTime(_) => "hold time",
+            ADSRParamKind::DecayTime(_) => "decay time",
+            ADSRParamKind::SustainLevel(_) => "sustain level",
+            ADSRParamKind::ReleaseTime(_) => "release time",
+            ADSRParamKind::AttackCurve(_) => "attack curve",
+            ADSRParamKind::DecayCurve(_) => "decay curve",
+            ADSRParamKind::ReleaseCurve(_) => "release curve",
+            ADSRParamKind::LoopStart(_) => "loop start",
+            ADSRParamKind::PeakLevel(_) => "peak level",
+            ADSRParamKind::StartLevel(_) => "start level",
+            ADSRParamKind::AttackShape(_) => "attack shape",
+            ADSRParamKind::DecayShape(_) => "decay shape",
+            ADSRParamKind::ReleaseShape(_) => "release shape",
+            ADSRParamKind::VelocityToPeak(_) => "velocity to peak",
+            ADSRParamKind::VelocityToSustain(_) => "velocity to sustain",
+            ADSRParamKind::KeyTracking(_) => "key tracking",
+            ADSRParamKind::VelocityResponse(_) => "velocity response",
+        }
+    }
+
+    // range a numeric parameter has to be in, None for shapes
+    fn range(&self) -> Option<(f32, f32)> {
+        match *self {
+            ADSRParamKind::DelayTime(_) | ADSRParamKind::AttackTime(_) | ADSRParamKind::HoldTime(_)
+            | ADSRParamKind::DecayTime(_) | ADSRParamKind::ReleaseTime(_) | ADSRParamKind::LoopStart(_) => {
+                Some((0.0, f32::INFINITY))
             },
-            ADSRParamKind::AttackCurve(c) => {
-                c >= -1.0 && c <= 1.0
+            ADSRParamKind::AttackCurve(_) | ADSRParamKind::DecayCurve(_) | ADSRParamKind::ReleaseCurve(_) => {
+                Some((-1.0, 1.0))
             },
-            ADSRParamKind::DecayCurve(c) => {
-                c >= -1.0 && c <= 1.0
+            ADSRParamKind::SustainLevel(_) | ADSRParamKind::PeakLevel(_) | ADSRParamKind::StartLevel(_)
+            | ADSRParamKind::VelocityToPeak(_) | ADSRParamKind::VelocityToSustain(_) | ADSRParamKind::KeyTracking(_) => {
+                Some((0.0, 1.0))
             },
-            ADSRParamKind::ReleaseCurve(c) => {
-                c >= -1.0 && c <= 1.0
+            ADSRParamKind::AttackShape(_) | ADSRParamKind::DecayShape(_) | ADSRParamKind::ReleaseShape(_)
+            | ADSRParamKind::VelocityResponse(_) => None,
+        }
+    }
+
+    pub fn check(&self) -> Result<(), ADSRError> {
+        let param = self.name();
+        match (self.numeric_value(), self.range()) {
+            (Some(value), _) if !value.is_finite() => Err(ADSRError::NotFinite { param, value }),
+            (Some(value), Some((min, max))) if (min..=max).contains(&value) => Ok(()),
+            (Some(value), Some((min, max))) => Err(ADSRError::OutOfRange { param, value, min, max }),
+            _ => match *self {
+                ADSRParamKind::AttackShape(ref c) | ADSRParamKind::DecayShape(ref c) | ADSRParamKind::ReleaseShape(ref c)
+                    if c.is_valid() => Ok(()),
+                ADSRParamKind::VelocityResponse(ref v) if v.is_valid() => Ok(()),
+                _ => Err(ADSRError::InvalidShape { param }),
             },
         }
     }
+
+    pub fn is_valid(&self) -> bool {
+        self.check().is_ok()
+    }
 }
 
 #[derive(Clone)]
 pub struct ADSRParams {
+    delay_time    : f32,
     attack_time   : f32,
+    hold_time     : f32,
     decay_time    : f32,
     sustain_level : f32,
     release_time  : f32,
-    attack_curve  : f32,
-    decay_curve   : f32,
-    release_curve : f32,
+    attack_curve  : CurveKind,
+    decay_curve   : CurveKind,
+    release_curve : CurveKind,
+    loop_start    : f32,
+    peak_level    : f32,
+    start_level   : f32,
+    velocity_to_peak    : f32,
+    velocity_to_sustain : f32,
+    key_tracking  : f32,
+    velocity_curve : VelocityCurve,
+    curve_table_size : usize,       // 0 evaluates the curves directly
+    curve_tables  : [Option<CurveKind>; 3], // attack, decay and release curves sampled into tables
+    fast_math     : bool,
+    always_peak   : bool,           // the attack reaches the peak even without a decay
 }
 
 impl ADSRParams {
+    // panics on an invalid argument, see try_new()
     pub fn new(
         attack_time: f32, decay_time: f32, sustain_level: f32, release_time: f32,
         attack_curve: f32, decay_curve: f32, release_curve: f32
     ) -> Self {
-        assert!(ADSRParamKind::AttackTime(attack_time).is_valid());
-        assert!(ADSRParamKind::DecayTime(decay_time).is_valid());
-        assert!(ADSRParamKind::SustainLevel(sustain_level).is_valid());
-        assert!(ADSRParamKind::ReleaseTime(release_time).is_valid());
-        assert!(ADSRParamKind::AttackCurve(attack_curve).is_valid());
-        assert!(ADSRParamKind::DecayCurve(decay_curve).is_valid()); 
-        assert!(ADSRParamKind::ReleaseCurve(release_curve).is_valid()); 
-
-        ADSRParams {
+        match Self::try_new(attack_time, decay_time, sustain_level, release_time, attack_curve, decay_curve, release_curve) {
+            Ok(params) => params,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    // the first argument out of range is the error
+    pub fn try_new(
+        attack_time: f32, decay_time: f32, sustain_level: f32, release_time: f32,
+        attack_curve: f32, decay_curve: f32, release_curve: f32
+    ) -> Result<Self, ADSRError> {
+        ADSRParamKind::AttackTime(attack_time).check()?;
+        ADSRParamKind::DecayTime(decay_time).check()?;
+        ADSRParamKind::SustainLevel(sustain_level).check()?;
+        ADSRParamKind::ReleaseTime(release_time).check()?;
+        ADSRParamKind::AttackCurve(attack_curve).check()?;
+        ADSRParamKind::DecayCurve(decay_curve).check()?;
+        ADSRParamKind::ReleaseCurve(release_curve).check()?;
+
+        Ok(ADSRParams {
+            delay_time: 0.0,
             attack_time,
-            attack_curve,
+            attack_curve: CurveKind::Factor(attack_curve),
+            hold_time: 0.0,
             decay_time,
-            decay_curve,
+            decay_curve: CurveKind::Factor(decay_curve),
             sustain_level,
             release_time,
-            release_curve,
+            release_curve: CurveKind::Factor(release_curve),
+            loop_start: 0.0,
+            peak_level: 1.0,
+            start_level: 0.0,
+            velocity_to_peak: 0.0,
+            velocity_to_sustain: 0.0,
+            key_tracking: 0.0,
+            velocity_curve: VelocityCurve::Linear,
+            curve_table_size: 0,
+            curve_tables: [None, None, None],
+            fast_math: false,
+            always_peak: false,
+        })
+    }
+
+    // evaluate the stage curves from tables of size points rebuilt whenever a curve changes,
+    // trading a little memory and accuracy for no transcendental math while the envelope runs.
+    // 0 goes back to evaluating the curves directly
+    pub fn set_curve_table(&mut self, size: usize) {
+        assert!(size == 0 || size >= 2);
+        self.curve_table_size = size;
+        for stage in 0..3 {
+            self.update_table(stage);
+        }
+    }
+
+    // evaluate Factor and SCurve shapes with a polynomial approximation of powf, to within
+    // about 0.01% of the level, for banks of hundreds of voices that can afford a tiny shape
+    // error. Factor stages mostly step a recurrence anyway, this covers what evaluates the curve
+    pub fn set_fast_math(&mut self, on: bool) {
+        self.fast_math = on;
+    }
+
+    // with a decay time of 0 the attack heads for the sustain level, so the note never rises
+    // past it. on, the attack reaches the peak all the same and the level drops straight to
+    // sustain on the sample after it (and after the hold), the classic shape
+    pub fn set_always_peak(&mut self, on: bool) {
+        self.always_peak = on;
+    }
+
+    // a copy with curve tables of its own, so each can change its curves in place
+    pub(crate) fn unshared(&self) -> Self {
+        let mut params = self.clone();
+        for table in params.curve_tables.iter_mut() {
+            if let Some(CurveKind::Table(levels)) = table {
+                *levels = Arc::from(&levels[..]);
+            }
+        }
+        params
+    }
+
+    // like clone_from, but the levels of the tables go into own tables of the same size,
+    // keeping them unshared without allocating
+    pub(crate) fn copy_from(&mut self, other: &ADSRParams) {
+        let mut own = core::mem::take(&mut self.curve_tables);
+        self.clone_from(other);
+        for (table, own) in self.curve_tables.iter_mut().zip(own.iter_mut()) {
+            let (Some(CurveKind::Table(levels)), Some(CurveKind::Table(own_levels))) = (&*table, &mut *own) else {
+                continue;
+            };
+            if let Some(own_levels) = Arc::get_mut(own_levels).filter(|l| l.len() == levels.len()) {
+                own_levels.copy_from_slice(levels);
+                *table = own.take();
+            }
+        }
+    }
+
+    // refilled in place when the table is not shared, so changing a curve does not allocate
+    fn update_table(&mut self, stage: usize) {
+        let curve = [&self.attack_curve, &self.decay_curve, &self.release_curve][stage];
+        let size = self.curve_table_size;
+        if size == 0 || matches!(*curve, CurveKind::Linear | CurveKind::Table(_)) { // nothing to save
+            self.curve_tables[stage] = None;
+            return;
+        }
+        if let Some(CurveKind::Table(levels)) = &mut self.curve_tables[stage] {
+            if let Some(levels) = Arc::get_mut(levels).filter(|levels| levels.len() == size) {
+                curve.fill_table(levels);
+                return;
+            }
         }
+        self.curve_tables[stage] = Some(curve.table(size));
     }
 
+    // shapes the stages follow, tabulated if tables are enabled
+    pub(crate) fn attack_shape(&self) -> &CurveKind {
+        self.curve_tables[0].as_ref().unwrap_or(&self.attack_curve)
+    }
+
+    pub(crate) fn decay_shape(&self) -> &CurveKind {
+        self.curve_tables[1].as_ref().unwrap_or(&self.decay_curve)
+    }
+
+    pub(crate) fn release_shape(&self) -> &CurveKind {
+        self.curve_tables[2].as_ref().unwrap_or(&self.release_curve)
+    }
+
+    // panics on an invalid parameter, see try_set_param()
     pub fn set_param(&mut self, param: ADSRParamKind) {
-        assert!(param.is_valid());
+        if let Err(error) = self.try_set_param(param) {
+            panic!("{error}");
+        }
+    }
+
+    // leaves the parameters as they were if param is invalid
+    pub fn try_set_param(&mut self, param: ADSRParamKind) -> Result<(), ADSRError> {
+        param.check()?;
+        let table = match param {
+            ADSRParamKind::AttackCurve(_) | ADSRParamKind::AttackShape(_) => Some(0),
+            ADSRParamKind::DecayCurve(_) | ADSRParamKind::DecayShape(_) => Some(1),
+            ADSRParamKind::ReleaseCurve(_) | ADSRParamKind::ReleaseShape(_) => Some(2),
+            _ => None,
+        };
         match param {
+            ADSRParamKind::DelayTime(t) => {
+                self.delay_time = t;
+            },
             ADSRParamKind::AttackTime(t) => {
                 self.attack_time = t;
             },
+            ADSRParamKind::HoldTime(t) => {
+                self.hold_time = t;
+            },
             ADSRParamKind::DecayTime(t) => {
                 self.decay_time = t;
             },
@@ -105,273 +688,4022 @@ impl ADSRParams {
                 self.release_time = t;
             },
             ADSRParamKind::AttackCurve(c) => {
-                self.attack_curve = c;
+                self.attack_curve = CurveKind::Factor(c);
             },
             ADSRParamKind::DecayCurve(c) => {
-                self.decay_curve = c;
+                self.decay_curve = CurveKind::Factor(c);
             },
             ADSRParamKind::ReleaseCurve(c) => {
+                self.release_curve = CurveKind::Factor(c);
+            },
+            ADSRParamKind::LoopStart(t) => {
+                self.loop_start = t;
+            },
+            ADSRParamKind::PeakLevel(l) => {
+                self.peak_level = l;
+            },
+            ADSRParamKind::StartLevel(l) => {
+                self.start_level = l;
+            },
+            ADSRParamKind::AttackShape(c) => {
+                self.attack_curve = c;
+            },
+            ADSRParamKind::DecayShape(c) => {
+                self.decay_curve = c;
+            },
+            ADSRParamKind::ReleaseShape(c) => {
                 self.release_curve = c;
-            }
+            },
+            ADSRParamKind::VelocityToPeak(a) => {
+                self.velocity_to_peak = a;
+            },
+            ADSRParamKind::VelocityToSustain(a) => {
+                self.velocity_to_sustain = a;
+            },
+            ADSRParamKind::KeyTracking(a) => {
+                self.key_tracking = a;
+            },
+            ADSRParamKind::VelocityResponse(v) => {
+                self.velocity_curve = v;
+            },
+        }
+        if let Some(stage) = table {
+            self.update_table(stage);
         }
+        Ok(())
     }
+
+    // current value of a numeric parameter of the same kind as param, the kind's constructor and
+    // the value param sets. None for shapes, and curve factors of a stage with another shape
+    pub(crate) fn numeric(&self, param: &ADSRParamKind) -> Option<(f32, ParamConstructor, f32)> {
+        let factor = |curve: &CurveKind| match *curve {
+            CurveKind::Factor(c) => Some(c),
+            _ => None,
+        };
+        let (current, make, target): (_, ParamConstructor, _) = match *param {
+            ADSRParamKind::DelayTime(t) => (Some(self.delay_time), ADSRParamKind::DelayTime, t),
+            ADSRParamKind::AttackTime(t) => (Some(self.attack_time), ADSRParamKind::AttackTime, t),
+            ADSRParamKind::HoldTime(t) => (Some(self.hold_time), ADSRParamKind::HoldTime, t),
+            ADSRParamKind::DecayTime(t) => (Some(self.decay_time), ADSRParamKind::DecayTime, t),
+            ADSRParamKind::SustainLevel(l) => (Some(self.sustain_level), ADSRParamKind::SustainLevel, l),
+            ADSRParamKind::ReleaseTime(t) => (Some(self.release_time), ADSRParamKind::ReleaseTime, t),
+            ADSRParamKind::AttackCurve(c) => (factor(&self.attack_curve), ADSRParamKind::AttackCurve, c),
+            ADSRParamKind::DecayCurve(c) => (factor(&self.decay_curve), ADSRParamKind::DecayCurve, c),
+            ADSRParamKind::ReleaseCurve(c) => (factor(&self.release_curve), ADSRParamKind::ReleaseCurve, c),
+            ADSRParamKind::LoopStart(t) => (Some(self.loop_start), ADSRParamKind::LoopStart, t),
+            ADSRParamKind::PeakLevel(l) => (Some(self.peak_level), ADSRParamKind::PeakLevel, l),
+            ADSRParamKind::StartLevel(l) => (Some(self.start_level), ADSRParamKind::StartLevel, l),
+            ADSRParamKind::VelocityToPeak(a) => (Some(self.velocity_to_peak), ADSRParamKind::VelocityToPeak, a),
+            ADSRParamKind::VelocityToSustain(a) => (Some(self.velocity_to_sustain), ADSRParamKind::VelocityToSustain, a),
+            ADSRParamKind::KeyTracking(a) => (Some(self.key_tracking), ADSRParamKind::KeyTracking, a),
+            ADSRParamKind::AttackShape(_) | ADSRParamKind::DecayShape(_) | ADSRParamKind::ReleaseShape(_)
+            | ADSRParamKind::VelocityResponse(_) => {
+                return None;
+            },
+        };
+        current.map(|current| (current, make, target))
+    }
+}
+
+// what the event queue holds: events, and the calls acting on the gate, so that everything
+// done to the gate before a sample applies in the order it was called
+#[derive(Clone, Copy)]
+enum Action {
+    Event(ADSREvent),
+    Pedal(f32),   // set_sustain_pedal_level()
+    Trigger,      // trigger()
+    GateFor(u64), // trigger_for_samples()
+    Close,        // the gate closing of kill()
+}
+
+// linear fade used by kill()
+#[derive(Clone)]
+struct Fade {
+    from: f32,
+    samples: u32,
+    position: u32,
+}
+
+// linear crossfade from a frozen value into the running timeline, used when a legato
+// note on arrives while the envelope still sounds
+#[derive(Clone)]
+struct Bridge {
+    from: f32,
+    samples: u32,
+    position: u32,
+}
+
+// linear glide of one parameter toward a value set with smoothing
+#[derive(Clone)]
+struct Slew {
+    make: ParamConstructor,
+    value: f32,
+    target: f32,
+    step: f32,
+    left: u32,
+}
+
+// blend from the trajectory of replaced parameters into the one of the current parameters
+#[derive(Clone)]
+struct ParamFade {
+    old: Arc<ADSRParams>,
+    samples: u32,
+    position: u32,
+}
+
+// where the envelope is within one stage and how many samples are left before the next one
+// has to be looked up: the level is base + scale * g, g advancing as g * mul + add from one
+// sample to the next. laid out when a stage is entered and again every CURSOR_SPAN samples,
+// which bounds the rounding drift, or once per control period as a straight line. one-pole
+// cursors fold base and scale into the recurrence and run to the end of the stage, as do
+// cursors of shapes that cannot be stepped, which evaluate the curve of the stage instead
+#[derive(Clone, Copy)]
+pub(crate) struct Cursor {
+    pub(crate) base: Real,
+    pub(crate) scale: Real,
+    pub(crate) g: Real,
+    pub(crate) mul: Real,
+    pub(crate) add: Real,
+    pub(crate) length: usize, // samples covered
+    position: usize,          // samples stepped so far
+    phase: ADSRPhase,
+    note_on: bool,  // which timeline
+    duration: u64,  // timeline position of the first sample
+    step: u64,      // timeline advance per sample
+    evaluated: Option<usize>, // index of the stage evaluated, g being the second it starts at
+}
+
+const CURSOR_SPAN: usize = 64;
+
+// timelines are counted in ticks, fractions of a sample, so positions stay exact however long a
+// note lasts while a half-pressed pedal or a new sample rate can still move them by less than
+// a sample
+const TICKS: u64 = 1 << 16;
+
+// where a voice is in its note, from ADSR::save_state(), to be put back with restore_state()
+#[derive(Clone)]
+pub struct ADSRState {
+    note_on_duration: u64,
+    note_off_duration: u64,
+    last_gate_val: f32,
+    current_event: ADSREvent,
+    current_phase: ADSRPhase,
+    current_val: f32,
+    next_event: ADSREvent,
+    gate_remaining: Option<u64>,
+    loops_done: u32,
+    fade: Option<Fade>,
+    velocity: f32,
+    next_velocity: f32,
+    key: u8,
+    release_pending: bool,
+    restart_pending: bool,
+    shot: bool,
+    attack_origin: Option<f32>,
+    bridge: Option<Bridge>,
+    clock: u64,
+    reported_phase: ADSRPhase,
+    phase_started: u64,
+    cursor: Option<Cursor>,
+    next_key: u8,
+    sustain_pedal: f32,
+    paused: bool,
+    scheduled: VecDeque<(u64, Action)>,
+    rng: u64,
+    jitter: [f32; 7],
+    param_fade: Option<ParamFade>,
 }
 
 pub struct ADSR {
-    params: ADSRParams,
-    note_on_duration: f32,
-    note_off_duration: f32,
+    params: Arc<ADSRParams>, // copied on write when shared with other voices
+    note_on_duration: u64,   // ticks
+    note_off_duration: u64,
     last_gate_val: f32,
     current_event: ADSREvent,
     current_phase: ADSRPhase,
     current_val: f32,
     next_event: ADSREvent,
     sample_rate: f32,
+    sample_period: f64, // 1 / sample_rate, in f64 so that multiplying by it rounds as dividing would
+    mode: EnvelopeMode,
+    polarity: Polarity,
+    level_scale: LevelScale,
+    output_range: (f32, f32), // where output 0 and 1 land
+    depth: f32,
+    inverted: bool,
+    gate_remaining: Option<u64>, // samples left before the scheduled note off
+    loop_count: LoopCount,
+    loops_done: u32,
+    fade: Option<Fade>,
+    paused: bool,
+    velocity: f32,      // of the sounding note
+    next_velocity: f32, // latched into velocity when the next note starts
+    key: u8,            // MIDI note number of the sounding note
+    next_key: u8,
+    sustain_pedal: f32, // 0 up, 1 fully down, half pedal in between
+    release_pending: bool, // note off received while the pedal is down
+    restart_pending: bool, // start the note again at the next sample even if the gate is already open
+    shot: bool,            // running a trigger() whatever the mode
+    retrigger_mode: RetriggerMode,
+    attack_origin: Option<f32>, // level the attack starts from when it did not start from scratch
+    bridge: Option<Bridge>,
+    clock: u64,                             // samples generated so far
+    scheduled: VecDeque<(u64, Action)>,     // sorted by sample
+    reported_phase: ADSRPhase,              // phase last passed to on_phase_change
+    on_phase_change: Option<Box<dyn FnMut(ADSRPhase, ADSRPhase) + Send>>,
+    param_policy: ParamPolicy,
+    on_param_clamped: Option<Box<dyn FnMut(ADSRError) + Send>>,
+    #[cfg(feature = "std")]
+    phase_sender: Option<SyncSender<(u64, ADSRPhase)>>,
+    #[cfg(feature = "debug-validate")]
+    violation: Option<validate::Violation>,
+    phase_started: u64, // clock() of the first sample of the current phase
+    control_period: u32,
+    one_pole: bool,
+    cursor: Option<Cursor>,
+    time_scale: f32, // multiplies every stage time
+    min_ramp: f32,   // shortest attack and release, seconds
+    bpm: f32,
+    synced_beats: [Option<f32>; 5], // stage lengths in beats, in the order of SYNCED_STAGES
+    humanize_time: f32,
+    humanize_level: f32,
+    seed: u64,
+    rng: u64,
+    jitter: [f32; 7], // factors of the sounding note: the times of SYNCED_STAGES, then peak and sustain level
+    param_fade_samples: u32,
+    param_fade: Option<ParamFade>,
+    smoothing_samples: u32,
+    slews: Vec<Slew>,
+    realtime: bool,
+    spare_params: Option<Arc<ADSRParams>>, // swapped in at a crossfade so it need not allocate
+    dropped_events: u32,
+    spare_scheduled: VecDeque<(u64, Action)>, // what dry runs play on, so peek() need not allocate
+    spare_slews: Vec<Slew>,
 }
 
+// scheduled events held without allocating in real-time safe mode, later ones are dropped
+const REALTIME_EVENTS: usize = 256;
+
+// stages whose time can follow the tempo
+const SYNCED_STAGES: [ADSRPhase; 5] =
+    [ADSRPhase::Delay, ADSRPhase::Attack, ADSRPhase::Hold, ADSRPhase::Decay, ADSRPhase::Release];
+
 impl ADSR {
     pub fn new(a: f32, d: f32, s: f32, r: f32, sample_rate: f32) -> Self {
+        Self::with_params(ADSRParams::new(a, d, s, r, 0.0, 0.0, 0.0), sample_rate)
+    }
+
+    pub fn try_new(a: f32, d: f32, s: f32, r: f32, sample_rate: f32) -> Result<Self, ADSRError> {
+        Ok(Self::with_params(ADSRParams::try_new(a, d, s, r, 0.0, 0.0, 0.0)?, sample_rate))
+    }
+
+    pub fn with_params(params: ADSRParams, sample_rate: f32) -> Self {
+        Self::with_shared_params(Arc::new(params), sample_rate)
+    }
+
+    pub(crate) fn with_shared_params(params: Arc<ADSRParams>, sample_rate: f32) -> Self {
         ADSR {
-            params: ADSRParams::new(a, d, s, r, 0.0, 0.0, 0.0),
-            note_on_duration: 0.0,
-            note_off_duration: 0.0,
+            params,
+            note_on_duration: 0,
+            note_off_duration: 0,
             last_gate_val: 0.0,
             current_event: ADSREvent::NoteOff,
             current_phase: ADSRPhase::Silence,
             current_val: 0.0,
             next_event: ADSREvent::NoteOff,
             sample_rate,
+            sample_period: 1.0 / sample_rate as f64,
+            mode: EnvelopeMode::Gated,
+            polarity: Polarity::Unipolar,
+            level_scale: LevelScale::Linear,
+            output_range: (0.0, 1.0),
+            depth: 1.0,
+            inverted: false,
+            gate_remaining: None,
+            loop_count: LoopCount::Infinite,
+            loops_done: 0,
+            fade: None,
+            paused: false,
+            velocity: 1.0,
+            next_velocity: 1.0,
+            key: 60,
+            next_key: 60,
+            sustain_pedal: 0.0,
+            release_pending: false,
+            restart_pending: false,
+            shot: false,
+            retrigger_mode: RetriggerMode::HardReset,
+            attack_origin: None,
+            bridge: None,
+            clock: 0,
+            scheduled: VecDeque::new(),
+            reported_phase: ADSRPhase::Silence,
+            on_phase_change: None,
+            param_policy: ParamPolicy::Reject,
+            on_param_clamped: None,
+            #[cfg(feature = "std")]
+            phase_sender: None,
+            #[cfg(feature = "debug-validate")]
+            violation: None,
+            phase_started: 0,
+            control_period: 1,
+            one_pole: false,
+            cursor: None,
+            time_scale: 1.0,
+            min_ramp: 0.0,
+            bpm: 120.0,
+            synced_beats: [None; 5],
+            humanize_time: 0.0,
+            humanize_level: 0.0,
+            seed: 0,
+            rng: 0,
+            jitter: [1.0; 7],
+            param_fade_samples: 0,
+            param_fade: None,
+            smoothing_samples: 0,
+            slews: Vec::new(),
+            realtime: false,
+            spare_params: None,
+            dropped_events: 0,
+            spare_scheduled: VecDeque::new(),
+            spare_slews: Vec::new(),
         }
     }
 
+    // an invalid parameter is handled as set_param_policy() says, clamped in real-time safe
+    // mode whatever the policy
     pub fn set_param(&mut self, param: ADSRParamKind) {
-        self.params.set_param(param);
-    }
-
-    pub fn set_next_event(&mut self, event: ADSREvent) {
-        self.next_event = event;
-    }
-
-    pub fn generate(&mut self) -> f32 {
-        match self.next_event {
-            ADSREvent::NoteOn => {
-                if self.current_event == ADSREvent::NoteOff {
-                    self.retrigger();
+        let param = match param.check() {
+            Ok(()) => param,
+            Err(error) if self.realtime || self.param_policy == ParamPolicy::Clamp => {
+                if let Some(callback) = self.on_param_clamped.as_mut() {
+                    callback(error);
                 }
-
-                let next_phase = self.next_phase(self.next_event);
-                let next_val = self.next_val(next_phase);
-
-                if self.current_phase != ADSRPhase::Sustain {
-                    self.note_on_duration += 1.0;
+                match param.clamped() {
+                    Some(param) => param,
+                    None => return, // nothing sensible to clamp to
                 }
-
-                self.current_event = self.next_event;
-                self.current_phase = next_phase;
-                self.current_val   = next_val;
-                next_val
             },
-            ADSREvent::NoteOff => {
-                if self.current_event == ADSREvent::NoteOn {
-                    self.last_gate_val = self.current_val; // remember last sample value before note off
-                }
-
-                let next_phase = self.next_phase(self.next_event);
-                let next_val = self.next_val(next_phase);
+            Err(error) => panic!("{error}"),
+        };
+        self.apply_param(param);
+    }
 
-                if self.current_phase != ADSRPhase::Silence {
-                    self.note_off_duration += 1.0;
-                }
+    // leaves the envelope as it was if param is invalid, whatever the policy
+    pub fn try_set_param(&mut self, param: ADSRParamKind) -> Result<(), ADSRError> {
+        param.check()?;
+        self.apply_param(param);
+        Ok(())
+    }
 
-                self.current_event = self.next_event;
-                self.current_phase = next_phase;
-                self.current_val   = next_val;
-                next_val
-            }
-        }
+    pub fn set_param_policy(&mut self, policy: ParamPolicy) {
+        self.param_policy = policy;
     }
 
-    fn retrigger(&mut self) {
-        self.note_on_duration  = 0.0;
-        self.note_off_duration = 0.0;
+    // called from set_param() with the error of each value it clamps or ignores instead of
+    // panicking, e.g. to log automation running out of range
+    pub fn on_param_clamped<F: FnMut(ADSRError) + Send + 'static>(&mut self, callback: F) {
+        self.on_param_clamped = Some(Box::new(callback));
     }
 
-    fn next_phase(&self, next_event: ADSREvent) -> ADSRPhase {
-        match next_event {
-            ADSREvent::NoteOn => {
-                let t = self.note_on_duration / self.sample_rate;
-                if t < self.params.attack_time {
-                    ADSRPhase::Attack
-                } else if t < self.params.decay_time + self.params.attack_time {
-                    ADSRPhase::Decay
-                } else { // if attack_time + decay_time <= t {
-                    ADSRPhase::Sustain
-                }
-            },
-            ADSREvent::NoteOff => {
-                let t = self.note_off_duration / self.sample_rate;
-                if t < self.params.release_time {
-                    ADSRPhase::Release
-                } else {
-                    ADSRPhase::Silence
-                }
+    fn apply_param(&mut self, param: ADSRParamKind) {
+        // a time in seconds replaces a tempo synced one
+        if let Some(index) = Self::synced_index(&param) {
+            self.synced_beats[index] = None;
+        }
+        if self.smoothing_samples > 0 {
+            if let Some((value, make, target)) = self.params.numeric(&param) {
+                let kind = core::mem::discriminant(&param);
+                self.slews.retain(|slew| core::mem::discriminant(&(slew.make)(0.0)) != kind);
+                let left = self.smoothing_samples;
+                self.slews.push(Slew { make, value, target, step: (target - value) / left as f32, left });
+                return;
             }
         }
+        self.begin_param_fade();
+        let point = self.timeline_point();
+        Arc::make_mut(&mut self.params).set_param(param);
+        self.retarget(point);
+        self.cursor = None;
     }
 
-    fn next_val(&self, next_phase: ADSRPhase) -> f32 {
-        match next_phase {
-            ADSRPhase::Attack => {
-                let t = self.note_on_duration / self.sample_rate;
-                if self.params.decay_time > 0.0 {
-                    Self::curve_function(t, 1.0, self.params.attack_time, self.params.attack_curve)
-                } else {
-                    Self::curve_function(t, self.params.sustain_level, self.params.attack_time, self.params.attack_curve)
-                }
-            },
-            ADSRPhase::Decay => {
-                let t = self.note_on_duration / self.sample_rate - self.params.attack_time;
-                Self::curve_function(self.params.decay_time - t, 1.0 - self.params.sustain_level, self.params.decay_time, self.params.decay_curve) + self.params.sustain_level
-            },
-            ADSRPhase::Sustain => {
-                self.params.sustain_level
-            },
-            ADSRPhase::Release => {
-                let t = self.note_off_duration / self.sample_rate;
-                Self::curve_function(self.params.release_time - t, self.last_gate_val, self.params.release_time, self.params.release_curve)
-            },
-            ADSRPhase::Silence => {
-                0.0
-            }
-        }
+    // numeric parameters set from now on move there linearly over time seconds, one step per
+    // sample, e.g. against zipper noise from host automation. shapes change at once. 0 disables
+    pub fn set_param_smoothing(&mut self, time: f32) {
+        assert!(time >= 0.0);
+        self.smoothing_samples = (time * self.sample_rate).round() as u32;
     }
 
-    // exponential curve that passes (0, 0) and (w, h)
-    fn curve_function(x: f32, h: f32, w: f32, curve_factor: f32) -> f32 {
-        assert!(x >= 0.0);
-        assert!(h >= 0.0);
-        assert!(w > 0.0);
-        assert!(curve_factor >= -1.0 && curve_factor <= 1.0);
-        if curve_factor == 0.0 { // linear
+    fn advance_slews(&mut self) {
+        if self.slews.is_empty() {
+            return;
+        }
+        let point = self.timeline_point();
+        let params = Arc::make_mut(&mut self.params);
+        for slew in self.slews.iter_mut() {
+            slew.left -= 1;
+            slew.value = if slew.left == 0 { slew.target } else { slew.value + slew.step };
+            params.set_param((slew.make)(slew.value));
+        }
+        self.slews.retain(|slew| slew.left > 0);
+        self.retarget(point);
+        self.cursor = None;
+    }
+
+    // where the sounding note is: which timeline, the stage, the seconds into it and its level.
+    // None between notes and during a parameter crossfade, which has its own way from the old
+    // trajectory to the new one
+    fn timeline_point(&self) -> Option<(bool, ADSRPhase, Real, f32)> {
+        if !self.is_active() || self.param_fade.is_some() {
+            return None;
+        }
+        let note_on = self.shot || self.mode == EnvelopeMode::OneShot || self.current_event != ADSREvent::NoteOff;
+        let point = |stages: &[Segment], duration: u64| {
+            locate_index(stages, self.seconds(duration)).map(|(i, t)| {
+                (note_on, stages[i].phase(), t, to_f32(stages[i].level_real(t)))
+            })
+        };
+        if note_on {
+            point(&self.note_on_stages(), self.note_on_duration)
+        } else {
+            point(&self.note_off_stages(), self.note_off_duration)
+        }
+    }
+
+    // after the parameters changed under the note at point, move the timeline to where the new
+    // stage passes the level the old one was at, so the envelope carries on from that level at
+    // the new rate instead of jumping to wherever the elapsed time lands under the new
+    // parameters. a level the new stage never passes, such as a decay whose sustain level rose
+    // above it, glides into the new stage over what is left of it
+    fn retarget(&mut self, point: Option<(bool, ADSRPhase, Real, f32)>) {
+        let Some((note_on, phase, elapsed, level)) = point else {
+            return;
+        };
+        let located = if note_on {
+            self.retarget_in(&self.note_on_stages(), phase, elapsed, level)
+        } else {
+            self.retarget_in(&self.note_off_stages(), phase, elapsed, level)
+        };
+        let Some((t, glide)) = located else {
+            return;
+        };
+        let ticks = (t * (self.sample_rate as f64 * TICKS as f64) as Real).round() as u64;
+        if note_on {
+            self.note_on_duration = ticks;
+        } else {
+            self.note_off_duration = ticks;
+        }
+        if glide > 0 {
+            // from what is heard, which a legato glide under way still pulls toward its start
+            let from = self.bridge.as_ref().map_or(level, |bridge| {
+                bridge.from + (level - bridge.from) * bridge.position as f32 / bridge.samples as f32
+            });
+            self.bridge = Some(Bridge { from, samples: glide, position: 0 });
+        }
+    }
+
+    // seconds into the timeline of stages where the stage of the given phase passes level, or
+    // where elapsed puts it for a flat stage, and the samples to glide over when that is not
+    // level. None when the timeline has no such stage any more
+    fn retarget_in(&self, stages: &[Segment], phase: ADSRPhase, elapsed: Real, level: f32) -> Option<(Real, u32)> {
+        let index = stages.iter().position(|stage| stage.phase() == phase)?;
+        let stage = &stages[index];
+        let start: Real = stages[..index].iter().map(|stage| stage.duration() as Real).sum();
+        let duration = stage.duration() as Real;
+        let (from, to) = (stage.from(), stage.target());
+        let t = if from != to && from.min(to) <= level && level <= from.max(to) && duration.is_finite() {
+            // the curves are monotonic, bisect for the time they pass level
+            let (mut lo, mut hi) = (0.0, duration);
+            for _ in 0..48 {
+                let mid = 0.5 * (lo + hi);
+                if (to_f32(stage.level_real(mid)) - level) * (to - from) < 0.0 {
+                    lo = mid;
+                } else {
+                    hi = mid;
+                }
+            }
+            hi
+        } else {
+            elapsed.min(duration)
+        };
+        let gap = to_f32(stage.level_real(t)) - level;
+        let glide = if gap.abs() > 1e-6 && duration.is_finite() {
+            to_f32((duration - t) * self.sample_rate as Real).round() as u32
+        } else {
+            0
+        };
+        Some((start + t, glide))
+    }
+
+    // index into synced_beats of the stage a time parameter belongs to
+    fn synced_index(param: &ADSRParamKind) -> Option<usize> {
+        match param {
+            ADSRParamKind::DelayTime(_) => Some(0),
+            ADSRParamKind::AttackTime(_) => Some(1),
+            ADSRParamKind::HoldTime(_) => Some(2),
+            ADSRParamKind::DecayTime(_) => Some(3),
+            ADSRParamKind::ReleaseTime(_) => Some(4),
+            _ => None,
+        }
+    }
+
+    // the value a numeric parameter of the same kind as param is at or gliding to. None for
+    // shapes, and for stage times following the tempo
+    pub(crate) fn param_target(&self, param: &ADSRParamKind) -> Option<f32> {
+        if Self::synced_index(param).is_some_and(|index| self.synced_beats[index].is_some()) {
+            return None;
+        }
+        let kind = core::mem::discriminant(param);
+        match self.slews.iter().find(|slew| core::mem::discriminant(&(slew.make)(0.0)) == kind) {
+            Some(slew) => Some(slew.target),
+            None => self.params.numeric(param).map(|(current, _, _)| current),
+        }
+    }
+
+    // glide over time seconds from the old to the new trajectory when a parameter changes during
+    // a note, instead of jumping to where the new parameters put the envelope. 0 jumps
+    pub fn set_param_crossfade(&mut self, time: f32) {
+        assert!(time >= 0.0);
+        self.param_fade_samples = (time * self.sample_rate).round() as u32;
+    }
+
+    // keep the parameters in use for the crossfade to whatever replaces them. a crossfade
+    // already under way goes on from its old parameters
+    fn begin_param_fade(&mut self) {
+        if self.param_fade_samples > 0 && self.is_active() && self.param_fade.is_none() {
+            // in real-time safe mode the new parameters go on in the spare copy, so changing
+            // them does not copy the old ones on write
+            let old = match self.spare_params.take() {
+                Some(mut spare) => match Arc::get_mut(&mut spare) {
+                    Some(params) => {
+                        params.copy_from(&self.params);
+                        core::mem::replace(&mut self.params, spare)
+                    },
+                    None => Arc::clone(&self.params),
+                },
+                None => Arc::clone(&self.params),
+            };
+            self.param_fade = Some(ParamFade { old, samples: self.param_fade_samples, position: 0 });
+        }
+    }
+
+    fn end_param_fade(&mut self) {
+        if let Some(fade) = self.param_fade.take() {
+            if self.realtime {
+                self.spare_params = Some(fade.old); // kept rather than freed on the audio thread
+            }
+        }
+    }
+
+    // from now on nothing called while generating allocates or panics: invalid parameters,
+    // velocities, pedal levels, keys and offsets are clamped into range or ignored when NaN,
+    // events scheduled past REALTIME_EVENTS pending are dropped, unless due by the next sample,
+    // and counted in dropped_events(). call it before the audio thread starts, it allocates what
+    // that needs. setup calls (configuration setters, save_state(), set_curve_table(), shared
+    // parameters) are not covered
+    pub fn set_realtime_safe(&mut self, on: bool) {
+        self.realtime = on;
+        if on {
+            if Arc::get_mut(&mut self.params).is_none() {
+                self.params = Arc::new(self.params.unshared());
+            }
+            self.scheduled.reserve(REALTIME_EVENTS.saturating_sub(self.scheduled.len()));
+            self.slews.reserve(ADSRParamKind::NUMERIC.saturating_sub(self.slews.len()));
+            self.spare_scheduled.reserve(self.scheduled.capacity());
+            self.spare_slews.reserve(self.slews.capacity());
+            if self.spare_params.is_none() {
+                self.spare_params = Some(Arc::new(self.params.unshared()));
+            }
+        } else {
+            self.spare_params = None;
+        }
+    }
+
+    pub fn is_realtime_safe(&self) -> bool {
+        self.realtime
+    }
+
+    // events dropped so far because the queue was full in real-time safe mode
+    pub fn dropped_events(&self) -> u32 {
+        self.dropped_events
+    }
+
+    // follow parameters shared with other voices, replacing the own ones and any tempo sync
+    pub(crate) fn set_shared_params(&mut self, params: Arc<ADSRParams>) {
+        self.begin_param_fade();
+        self.params = params;
+        self.slews.clear();
+        self.synced_beats = [None; 5];
+        self.cursor = None;
+    }
+
+    // vary every note a little: each stage time is scaled by a random factor within 1 ± time,
+    // peak and sustain level within 1 ± level, drawn anew at each note on from a generator
+    // started at seed, so a render can be repeated exactly
+    pub fn set_humanize(&mut self, time: f32, level: f32, seed: u64) {
+        assert!((0.0..=1.0).contains(&time));
+        assert!((0.0..=1.0).contains(&level));
+        self.humanize_time = time;
+        self.humanize_level = level;
+        self.seed = seed;
+        self.rng = seed;
+    }
+
+    // host tempo for the stages given in beats
+    pub fn set_bpm(&mut self, bpm: f32) {
+        assert!(bpm > 0.0 && bpm.is_finite());
+        self.bpm = bpm;
+        self.update_synced_times();
+    }
+
+    // length of a delay, attack, hold, decay or release stage in beats (quarter notes, so an
+    // eighth is 0.5 and a dotted quarter 1.5), following set_bpm(). None keeps the current seconds
+    pub fn set_stage_beats(&mut self, stage: ADSRPhase, beats: Option<f32>) {
+        let index = SYNCED_STAGES.iter().position(|&s| s == stage);
+        assert!(index.is_some());
+        assert!(beats.is_none_or(|b| b >= 0.0 && b.is_finite()));
+        self.synced_beats[index.unwrap()] = beats;
+        self.update_synced_times();
+    }
+
+    fn update_synced_times(&mut self) {
+        if self.synced_beats.iter().any(Option::is_some) {
+            self.begin_param_fade();
+        }
+        for (stage, beats) in SYNCED_STAGES.iter().zip(self.synced_beats) {
+            let Some(beats) = beats else {
+                continue;
+            };
+            let t = beats * 60.0 / self.bpm;
+            Arc::make_mut(&mut self.params).set_param(match stage {
+                ADSRPhase::Delay => ADSRParamKind::DelayTime(t),
+                ADSRPhase::Attack => ADSRParamKind::AttackTime(t),
+                ADSRPhase::Hold => ADSRParamKind::HoldTime(t),
+                ADSRPhase::Decay => ADSRParamKind::DecayTime(t),
+                _ => ADSRParamKind::ReleaseTime(t),
+            });
+        }
+        self.cursor = None;
+    }
+
+    // apply event right before the next sample, after the events already scheduled for it, so
+    // the samples depend on when events happen and not on how generation is split into calls.
+    // an explicit event cancels a pending automatic note off
+    pub fn set_next_event(&mut self, event: ADSREvent) {
+        self.schedule_event(self.clock, event);
+    }
+
+    fn apply_action(&mut self, action: Action) {
+        match action {
+            Action::Event(event) => self.apply_event(event),
+            Action::Pedal(level) => {
+                self.sustain_pedal = level;
+                if level < 1.0 && self.release_pending {
+                    self.release_pending = false;
+                    self.close_gate();
+                }
+            },
+            Action::Trigger => {
+                self.gate_remaining = None;
+                self.release_pending = false;
+                self.shot = true;
+                self.restart_pending = true;
+                self.next_velocity = 1.0;
+                self.next_event = ADSREvent::NoteOn;
+            },
+            Action::GateFor(gate_samples) => {
+                self.restart_pending = true; // also restart a note that is still held
+                self.open_gate();
+                self.next_velocity = 1.0;
+                self.gate_remaining = Some(gate_samples);
+            },
+            Action::Close => {
+                self.next_event = ADSREvent::NoteOff;
+                self.gate_remaining = None;
+                self.release_pending = false;
+                self.restart_pending = false;
+            },
+        }
+    }
+
+    fn apply_event(&mut self, event: ADSREvent) {
+        if self.shot {
+            return; // trigger() ignores the gate until it has finished
+        }
+        self.gate_remaining = None;
+        match event {
+            ADSREvent::NoteOnWithVelocity(v) => {
+                if self.realtime && v.is_nan() {
+                    return;
+                }
+                assert!(self.realtime || (0.0..=1.0).contains(&v));
+                self.next_velocity = v.clamp(0.0, 1.0);
+                self.open_gate();
+            },
+            ADSREvent::NoteOn => {
+                self.next_velocity = 1.0;
+                self.open_gate();
+            },
+            ADSREvent::Retrigger => {
+                self.restart_pending = true;
+                self.open_gate();
+            },
+            ADSREvent::NoteOff => {
+                self.close_gate();
+            }
+        }
+    }
+
+    // apply event right before the sample at_sample (counted by clock()) is generated,
+    // events for the same sample apply in the order they were scheduled, past ones right away.
+    // false if the event was dropped, see set_realtime_safe()
+    pub fn schedule_event(&mut self, at_sample: u64, event: ADSREvent) -> bool {
+        if let ADSREvent::NoteOnWithVelocity(v) = event {
+            assert!(self.realtime || (0.0..=1.0).contains(&v));
+        }
+        self.schedule(at_sample, Action::Event(event))
+    }
+
+    // what is scheduled up to the next sample, applied ahead of it
+    fn apply_due(&mut self) {
+        while let Some((_, action)) = self.scheduled.pop_front_if(|e| e.0 <= self.clock) {
+            self.apply_action(action);
+        }
+    }
+
+    // false if the queue was full in real-time safe mode and the action was dropped. one due
+    // by the next sample is never dropped: what is due before it is applied, then it is
+    fn schedule(&mut self, at_sample: u64, action: Action) -> bool {
+        if self.realtime && self.scheduled.len() >= self.scheduled.capacity() {
+            if at_sample > self.clock {
+                self.dropped_events = self.dropped_events.saturating_add(1);
+                return false;
+            }
+            self.apply_due();
+            if self.scheduled.len() >= self.scheduled.capacity() {
+                self.apply_action(action); // everything left is later
+                return true;
+            }
+        }
+        let index = self.scheduled.partition_point(|&(at, _)| at <= at_sample);
+        self.scheduled.insert(index, (at_sample, action));
+        true
+    }
+
+    // samples generated since the envelope was created, paused samples not counted
+    pub fn clock(&self) -> u64 {
+        self.clock
+    }
+
+    // while the pedal is down note offs are deferred until it is lifted
+    pub fn set_sustain_pedal(&mut self, down: bool) {
+        self.set_sustain_pedal_level(if down { 1.0 } else { 0.0 });
+    }
+
+    // continuous pedal for half pedaling: below fully down a release runs at 1 - level of its
+    // speed, so it stretches and shrinks smoothly as the pedal moves
+    pub fn set_sustain_pedal_level(&mut self, level: f32) {
+        if self.realtime && level.is_nan() {
+            return;
+        }
+        assert!(self.realtime || (0.0..=1.0).contains(&level));
+        self.schedule(self.clock, Action::Pedal(level.clamp(0.0, 1.0)));
+    }
+
+    fn open_gate(&mut self) {
+        if self.release_pending {
+            self.release_pending = false;
+            self.restart_pending = true; // struck again while held by the pedal
+        }
+        self.next_event = ADSREvent::NoteOn;
+    }
+
+    fn close_gate(&mut self) {
+        if self.sustain_pedal >= 1.0 && self.next_event == ADSREvent::NoteOn {
+            self.release_pending = true;
+        } else {
+            self.next_event = ADSREvent::NoteOff;
+            self.restart_pending = false;
+        }
+    }
+
+    // MIDI note number of the next note on, used for key tracking
+    pub fn set_key(&mut self, note: u8) {
+        assert!(self.realtime || note < 128);
+        self.next_key = note.min(127);
+    }
+
+    pub fn note_on(&mut self) {
+        self.set_next_event(ADSREvent::NoteOn);
+    }
+
+    pub fn note_off(&mut self) {
+        self.set_next_event(ADSREvent::NoteOff);
+    }
+
+    pub fn gate(&mut self, on: bool) {
+        if on {
+            self.note_on();
+        } else {
+            self.note_off();
+        }
+    }
+
+    // note on now and note off after gate_time seconds
+    pub fn trigger_for(&mut self, gate_time: f32) {
+        assert!(self.realtime || gate_time >= 0.0);
+        self.trigger_for_samples((gate_time * self.sample_rate).round() as u64);
+    }
+
+    // fire attack and decay to zero once, as in OneShot mode whatever the mode is.
+    // events arriving before the shot has finished are dropped
+    pub fn trigger(&mut self) {
+        self.schedule(self.clock, Action::Trigger);
+    }
+
+    pub fn trigger_for_samples(&mut self, gate_samples: u64) {
+        self.schedule(self.clock, Action::GateFor(gate_samples));
+    }
+
+    // compute the curves only every period samples (and at stage boundaries) and interpolate
+    // linearly in between, trading accuracy inside curved stages for speed. 1 computes every sample
+    pub fn set_control_period(&mut self, period: u32) {
+        assert!(period >= 1);
+        self.control_period = period;
+        self.cursor = None;
+    }
+
+    // look the stage curves up in tables of size points, see ADSRParams::set_curve_table.
+    // smoothed curve factors rebuild their table every sample, better left unsmoothed
+    pub fn set_curve_table(&mut self, size: usize) {
+        Arc::make_mut(&mut self.params).set_curve_table(size);
+        self.cursor = None;
+    }
+
+    pub fn set_fast_math(&mut self, on: bool) {
+        Arc::make_mut(&mut self.params).set_fast_math(on);
+        self.cursor = None;
+    }
+
+    pub fn set_always_peak(&mut self, on: bool) {
+        self.begin_param_fade();
+        Arc::make_mut(&mut self.params).set_always_peak(on);
+        self.cursor = None;
+    }
+
+    // attacks and releases shorter than time seconds, 0 included, take time instead, so a note
+    // starts and stops with a ramp rather than a jump that clicks. 1 to 5 ms is heard as a hard
+    // edge but not as a click. 0 disables
+    pub fn set_min_ramp(&mut self, time: f32) {
+        assert!(time >= 0.0 && time.is_finite());
+        self.begin_param_fade();
+        self.min_ramp = time;
+        self.cursor = None;
+    }
+
+    // step the stages that settle toward their level like an RC circuit (and the linear ones)
+    // as a one-pole recurrence, level = level * a + b, from start to end instead of laying out
+    // the curve again every few samples. cheaper, but rounding adds up over long stages so the
+    // levels only approximately match the curve factors
+    pub fn set_one_pole(&mut self, one_pole: bool) {
+        self.one_pole = one_pole;
+        self.cursor = None;
+    }
+
+    // multiply every stage time (and the loop start) by scale without touching the parameters,
+    // below 1.0 for a snappier patch, above for a slower one
+    pub fn set_time_scale(&mut self, scale: f32) {
+        assert!(scale > 0.0 && scale.is_finite());
+        self.time_scale = scale;
+        self.cursor = None;
+    }
+
+    // switch to another sample rate in the middle of a note: positions, fades and pending
+    // events keep their place in seconds, so the envelope carries on where it was
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        assert!(sample_rate > 0.0 && sample_rate.is_finite());
+        let ratio = sample_rate as f64 / self.sample_rate as f64;
+        let rescale = |samples: u64| (samples as f64 * ratio).round() as u64;
+        self.sample_rate = sample_rate;
+        self.sample_period = 1.0 / sample_rate as f64;
+        self.note_on_duration = rescale(self.note_on_duration);
+        self.note_off_duration = rescale(self.note_off_duration);
+        self.gate_remaining = self.gate_remaining.map(rescale);
+        if let Some(fade) = self.fade.as_mut() {
+            fade.samples = (rescale(fade.samples as u64) as u32).max(1);
+            fade.position = (rescale(fade.position as u64) as u32).min(fade.samples - 1);
+        }
+        if let Some(bridge) = self.bridge.as_mut() {
+            bridge.samples = (rescale(bridge.samples as u64) as u32).max(1);
+            bridge.position = (rescale(bridge.position as u64) as u32).min(bridge.samples - 1);
+        }
+        self.param_fade_samples = rescale(self.param_fade_samples as u64) as u32;
+        if let Some(fade) = self.param_fade.as_mut() {
+            fade.samples = (rescale(fade.samples as u64) as u32).max(1);
+            fade.position = (rescale(fade.position as u64) as u32).min(fade.samples - 1);
+        }
+        self.smoothing_samples = rescale(self.smoothing_samples as u64) as u32;
+        for slew in self.slews.iter_mut() {
+            slew.left = (rescale(slew.left as u64) as u32).max(1);
+            slew.step = (slew.target - slew.value) / slew.left as f32;
+        }
+        let clock = self.clock;
+        for (at, _) in self.scheduled.iter_mut() {
+            *at = clock + rescale(at.saturating_sub(clock));
+        }
+        self.phase_started = clock.saturating_sub(rescale(clock - self.phase_started));
+        self.cursor = None;
+    }
+
+    // the legato glide takes the attack time
+    pub fn set_retrigger_mode(&mut self, mode: RetriggerMode) {
+        self.retrigger_mode = mode;
+    }
+
+    pub fn set_mode(&mut self, mode: EnvelopeMode) {
+        self.mode = mode;
+        self.cursor = None;
+    }
+
+    // with LoopStart(0.0) the whole attack to decay cycle repeats
+    pub fn set_loop_count(&mut self, loop_count: LoopCount) {
+        self.loop_count = loop_count;
+    }
+
+    pub fn set_polarity(&mut self, polarity: Polarity) {
+        self.polarity = polarity;
+    }
+
+    // with Decibels the stages and their curves run on the level in dB, so a linear release
+    // falls at a constant dB per second the way mixer and sampler envelopes do. the level held
+    // now is carried over, the stages ahead take the new scale
+    pub fn set_level_scale(&mut self, scale: LevelScale) {
+        if let LevelScale::Decibels(range) = scale {
+            assert!(range > 0.0 && range.is_finite());
+        }
+        let current = self.amplitude(self.current_val);
+        let gate = self.amplitude(self.last_gate_val);
+        let origin = self.attack_origin.map(|l| self.amplitude(l));
+        self.level_scale = scale;
+        self.current_val = self.scaled(current);
+        self.last_gate_val = self.scaled(gate);
+        self.attack_origin = origin.map(|l| self.scaled(l));
+        self.cursor = None;
+    }
+
+    // the amount of the envelope, -1..1, scaling its effect around the bottom of the output
+    // range: with set_output_range(base, base + span) the output is base + span * depth * level,
+    // an "env amount" knob on a modulation destination. negative depths sweep below the base
+    pub fn set_depth(&mut self, depth: f32) {
+        if self.realtime && depth.is_nan() {
+            return;
+        }
+        assert!(self.realtime || (-1.0..=1.0).contains(&depth));
+        self.depth = depth.clamp(-1.0, 1.0);
+    }
+
+    // map the output from 0..1 onto min..max, after polarity, depth and inversion, e.g. 200.0
+    // to 8000.0 to drive a cutoff in Hz. bipolar outputs below 0 extend past min the same way.
+    // max may be below min to run the other way
+    pub fn set_output_range(&mut self, min: f32, max: f32) {
+        if self.realtime && !(min.is_finite() && max.is_finite()) {
+            return;
+        }
+        assert!(min.is_finite() && max.is_finite());
+        self.output_range = (min, max);
+    }
+
+    // output 1.0 - value, so the idle (silence) output is 1.0
+    pub fn set_inverted(&mut self, inverted: bool) {
+        self.inverted = inverted;
+    }
+
+    // fade linearly to silence over fade_samples, bypassing the release. the gate is
+    // closed, so a new note on starts only after the fade has finished
+    pub fn kill(&mut self, fade_samples: u32) {
+        self.schedule(self.clock, Action::Close);
+        self.current_event = ADSREvent::NoteOff;
+        self.shot = false;
+        self.bridge = None;
+        self.last_gate_val = 0.0;
+        if fade_samples == 0 || self.current_phase == ADSRPhase::Silence {
+            self.fade = None;
+            self.current_phase = ADSRPhase::Silence;
+            self.current_val = 0.0;
+        } else {
+            self.fade = Some(Fade { from: self.current_val, samples: fade_samples, position: 0 });
+            self.current_phase = ADSRPhase::Shutdown;
+        }
+    }
+
+    // false if anything the next sample is computed from is NaN or infinite. generate() silences
+    // the envelope as soon as a level stops being finite (a custom shape returning NaN, say), so
+    // this is for checking restored or otherwise suspect state before it is played
+    pub fn is_healthy(&self) -> bool {
+        let values = [
+            self.current_val,
+            self.last_gate_val,
+            self.velocity,
+            self.next_velocity,
+            self.sustain_pedal,
+            self.fade.as_ref().map_or(0.0, |fade| fade.from),
+            self.bridge.as_ref().map_or(0.0, |bridge| bridge.from),
+            self.attack_origin.unwrap_or(0.0),
+        ];
+        values.iter().all(|v| v.is_finite())
+            && self.cursor.is_none_or(|c| [c.base, c.scale, c.g, c.mul, c.add].iter().all(|v| v.is_finite()))
+    }
+
+    // back to silence after a level that is not finite, so it cannot carry into later samples
+    fn resilence(&mut self) {
+        self.kill(0);
+        self.cursor = None;
+        self.attack_origin = None;
+        self.end_param_fade();
+    }
+
+    // the runtime state of the envelope: phase, timelines, values, pending events and pedal.
+    // parameters, mode and output settings are not part of it
+    pub fn save_state(&self) -> ADSRState {
+        ADSRState {
+            note_on_duration: self.note_on_duration,
+            note_off_duration: self.note_off_duration,
+            last_gate_val: self.last_gate_val,
+            current_event: self.current_event,
+            current_phase: self.current_phase,
+            current_val: self.current_val,
+            next_event: self.next_event,
+            gate_remaining: self.gate_remaining,
+            loops_done: self.loops_done,
+            fade: self.fade.clone(),
+            velocity: self.velocity,
+            next_velocity: self.next_velocity,
+            key: self.key,
+            release_pending: self.release_pending,
+            restart_pending: self.restart_pending,
+            shot: self.shot,
+            attack_origin: self.attack_origin,
+            bridge: self.bridge.clone(),
+            clock: self.clock,
+            reported_phase: self.reported_phase,
+            phase_started: self.phase_started,
+            cursor: self.cursor,
+            next_key: self.next_key,
+            sustain_pedal: self.sustain_pedal,
+            paused: self.paused,
+            scheduled: self.scheduled.clone(),
+            rng: self.rng,
+            jitter: self.jitter,
+            param_fade: self.param_fade.clone(),
+        }
+    }
+
+    // continue from a state saved from this or another envelope
+    pub fn restore_state(&mut self, state: ADSRState) {
+        self.note_on_duration = state.note_on_duration;
+        self.note_off_duration = state.note_off_duration;
+        self.last_gate_val = state.last_gate_val;
+        self.current_event = state.current_event;
+        self.current_phase = state.current_phase;
+        self.current_val = state.current_val;
+        self.next_event = state.next_event;
+        self.gate_remaining = state.gate_remaining;
+        self.loops_done = state.loops_done;
+        self.fade = state.fade;
+        self.velocity = state.velocity;
+        self.next_velocity = state.next_velocity;
+        self.key = state.key;
+        self.release_pending = state.release_pending;
+        self.restart_pending = state.restart_pending;
+        self.shot = state.shot;
+        self.attack_origin = state.attack_origin;
+        self.bridge = state.bridge;
+        self.clock = state.clock;
+        self.reported_phase = state.reported_phase;
+        self.phase_started = state.phase_started;
+        self.cursor = state.cursor;
+        self.next_key = state.next_key;
+        self.sustain_pedal = state.sustain_pedal;
+        self.paused = state.paused;
+        self.scheduled = state.scheduled;
+        self.rng = state.rng;
+        self.jitter = state.jitter;
+        self.param_fade = state.param_fade;
+    }
+
+    // back to the silent state of a new envelope, for reusing a voice from a pool. parameters,
+    // mode and output settings are kept, events, pedal and clock start over
+    pub fn reset(&mut self) {
+        let fresh = ADSR::with_shared_params(Arc::clone(&self.params), self.sample_rate);
+        self.restore_state(fresh.save_state());
+        self.rng = self.seed;
+    }
+
+    // host panic or transport stop: drop scheduled events, lift the pedal and silence the note,
+    // either at once or over a ramp of about 1.5 ms
+    pub fn panic(&mut self, ramp: bool) {
+        self.scheduled.clear();
+        self.sustain_pedal = 0.0;
+        let fade_samples = if ramp { (0.0015 * self.sample_rate).round() as u32 } else { 0 };
+        self.kill(fade_samples);
+    }
+
+    // called with the old and the new phase from generate() whenever the phase differs from
+    // the one of the previous sample, e.g. to free a voice when it reaches Silence
+    pub fn on_phase_change<F: FnMut(ADSRPhase, ADSRPhase) + Send + 'static>(&mut self, callback: F) {
+        self.on_phase_change = Some(Box::new(callback));
+    }
+
+    // phase changes as (clock() of the sample entering the phase, phase) for another thread.
+    // messages that do not fit into the channel are dropped
+    #[cfg(feature = "std")]
+    pub fn set_phase_sender(&mut self, sender: SyncSender<(u64, ADSRPhase)>) {
+        self.phase_sender = Some(sender);
+    }
+
+    #[cfg(feature = "std")]
+    pub fn phase_channel(&mut self, capacity: usize) -> Receiver<(u64, ADSRPhase)> {
+        let (sender, receiver) = sync_channel(capacity);
+        self.set_phase_sender(sender);
+        receiver
+    }
+
+    // while paused the output holds its current value and neither time nor events advance
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn current_phase(&self) -> ADSRPhase {
+        self.current_phase
+    }
+
+    // what the last generate() returned
+    pub fn value(&self) -> f32 {
+        self.map_output(self.current_val)
+    }
+
+    // amplitude of the last sample before polarity, depth and inversion, 0..1
+    pub fn level(&self) -> f32 {
+        self.amplitude(self.current_val.clamp(0.0, 1.0))
+    }
+
+    // the same in dB, f32::NEG_INFINITY in silence
+    pub fn level_db(&self) -> f32 {
+        20.0 * self.level().log10()
+    }
+
+    // 0..1 through the current stage, 0 for stages that last until the gate changes
+    pub fn phase_progress(&self) -> f32 {
+        if let Some(fade) = &self.fade {
+            return fade.position as f32 / fade.samples as f32;
+        }
+        let located = match self.current_event {
+            ADSREvent::NoteOff => {
+                let (t, stages) = (self.seconds(self.note_off_duration), self.note_off_stages());
+                locate_index(&stages, t).map(|(i, t)| (stages[i].phase(), to_f32(t / stages[i].duration() as Real)))
+            },
+            _ => {
+                let (t, stages) = (self.seconds(self.note_on_duration), self.note_on_stages());
+                locate_index(&stages, t).map(|(i, t)| (stages[i].phase(), to_f32(t / stages[i].duration() as Real)))
+            }
+        };
+        match located {
+            Some((phase, progress)) if phase == self.current_phase && progress.is_finite() => progress.min(1.0),
+            _ => 0.0,
+        }
+    }
+
+    // seconds generated since the current phase began
+    pub fn elapsed(&self) -> f32 {
+        to_f32(self.seconds((self.clock - self.phase_started) * TICKS))
+    }
+
+    // samples left until Silence if the gate closed now, for sizing the tail of an offline render.
+    // None while a fully pressed sustain pedal holds the note
+    pub fn time_until_silence(&self) -> Option<u64> {
+        if let Some(fade) = &self.fade {
+            return Some((fade.samples - fade.position) as u64);
+        }
+        if self.current_phase == ADSRPhase::Silence {
+            return Some(0);
+        }
+        if self.shot || self.mode == EnvelopeMode::OneShot {
+            // the gate is ignored, the note on timeline runs out by itself
+            let length = (self.delay_time() + self.attack_time() + self.hold_time() + self.decay_time()) * self.sample_rate;
+            return Some((length - Self::samples(self.note_on_duration)).max(0.0).ceil() as u64);
+        }
+        let speed = 1.0 - self.sustain_pedal;
+        if speed <= 0.0 {
+            return None;
+        }
+        let release = self.release_time() * self.sample_rate;
+        let left = if self.current_event == ADSREvent::NoteOff {
+            release - Self::samples(self.note_off_duration)
+        } else {
+            release
+        };
+        Some((left.max(0.0) / speed).ceil() as u64)
+    }
+
+    // a note is under way, including its delay and release
+    pub fn is_active(&self) -> bool {
+        self.current_phase != ADSRPhase::Silence
+    }
+
+    // silent with the gate closed and nothing scheduled, the voice can be reused
+    pub fn is_finished(&self) -> bool {
+        !self.is_active() && self.next_event == ADSREvent::NoteOff && self.current_event == ADSREvent::NoteOff
+            && self.scheduled.is_empty()
+    }
+
+    pub fn generate(&mut self) -> f32 {
+        if self.paused {
+            return self.map_output(self.current_val);
+        }
+
+        #[cfg(feature = "debug-validate")]
+        let before = self.before_sample();
+        let val = self.generate_envelope();
+        #[cfg(feature = "debug-validate")]
+        self.validate(before, val);
+        let val = if val.is_finite() {
+            val
+        } else {
+            self.resilence();
+            0.0
+        };
+        if self.current_phase != self.reported_phase {
+            let from = core::mem::replace(&mut self.reported_phase, self.current_phase);
+            self.phase_started = self.clock - 1;
+            if let Some(callback) = self.on_phase_change.as_mut() {
+                callback(from, self.current_phase);
+            }
+            #[cfg(feature = "std")]
+            if let Some(sender) = &self.phase_sender {
+                let _ = sender.try_send((self.clock - 1, self.current_phase)); // never block the audio thread
+            }
+        }
+        self.map_output(val)
+    }
+
+    // one sample before the output stage
+    fn generate_envelope(&mut self) -> f32 {
+        self.apply_due();
+        self.clock += 1;
+        self.advance_slews();
+
+        if let Some(val) = self.generate_fade() {
+            return val;
+        }
+
+        if let Some(remaining) = self.gate_remaining {
+            if remaining == 0 {
+                self.close_gate();
+                self.gate_remaining = None;
+            } else {
+                self.gate_remaining = Some(remaining - 1);
+            }
+        }
+
+        let val = match self.mode {
+            _ if self.shot => self.generate_one_shot(),
+            EnvelopeMode::Gated | EnvelopeMode::Percussive | EnvelopeMode::SustainLoop | EnvelopeMode::ASR => self.generate_gated(),
+            EnvelopeMode::OneShot => self.generate_one_shot(),
+        };
+        self.generate_bridge(val)
+    }
+
+    // fill out with consecutive samples. runs of samples inside a stage are stepped in a tight
+    // loop without the per sample event and phase checks of generate(), which only takes the
+    // samples at stage boundaries and events, and stages it cannot step. same samples either way
+    pub fn fill_block(&mut self, out: &mut [f32]) {
+        let mut pos = 0;
+        while pos < out.len() {
+            match self.render_run(&mut out[pos..]) {
+                0 => {
+                    out[pos] = self.generate();
+                    pos += 1;
+                },
+                len => pos += len,
+            }
+        }
+    }
+
+    // generate() into a pipeline running in another float type, f64 offline renders mostly
+    pub fn generate_as<T: Float>(&mut self) -> T {
+        cast(self.generate())
+    }
+
+    // fill_block() into a buffer of another float type, in chunks so it need not allocate
+    pub fn fill_block_as<T: Float>(&mut self, out: &mut [T]) {
+        let mut chunk = [0.0; 64];
+        for block in out.chunks_mut(chunk.len()) {
+            let chunk = &mut chunk[..block.len()];
+            self.fill_block(chunk);
+            for (x, &y) in block.iter_mut().zip(chunk.iter()) {
+                *x = cast(y);
+            }
+        }
+    }
+
+    // the samples at the start of out that stay in the current stage, rendered as generate()
+    // would. returns how many, 0 if the next sample needs generate()
+    fn render_run(&mut self, out: &mut [f32]) -> usize {
+        let len = self.steady_samples(out.len());
+        if len == 0 {
+            return 0;
+        }
+        let note_on = self.mode == EnvelopeMode::OneShot || self.current_event != ADSREvent::NoteOff;
+        let constant = match self.current_phase {
+            ADSRPhase::Silence if !note_on || self.mode == EnvelopeMode::OneShot => Some(0.0),
+            ADSRPhase::Silence | ADSRPhase::Sustain if note_on => {
+                let (phase, level) = self.state_at(true, self.note_on_duration);
+                if phase != self.current_phase {
+                    return 0; // the parameters moved the timeline, generate() reports the change
+                }
+                Some(level)
+            },
+            _ => None,
+        };
+        if let Some(level) = constant {
+            out[..len].fill(self.map_output(level));
+            self.clock += len as u64;
+            self.current_val = level;
+            return len;
+        }
+
+        // continue the cursor generate() laid out, so the levels round the same
+        let (duration, step) = if note_on {
+            (self.note_on_duration, TICKS)
+        } else {
+            (self.note_off_duration, self.note_off_step())
+        };
+        let Some(mut cursor) = self.cursor.filter(|c| {
+            c.evaluated.is_none() && c.note_on == note_on && c.phase == self.current_phase && c.step == step
+                && c.position < c.length && duration == c.duration + c.position as u64 * c.step
+        }) else {
+            return 0;
+        };
+        let len = len.min(cursor.length - cursor.position);
+        let flush = matches!(cursor.phase, ADSRPhase::Release | ADSRPhase::Silence);
+        let mut level = self.current_val;
+        for sample in out[..len].iter_mut() {
+            level = to_f32(cursor.base + cursor.scale * cursor.g);
+            if flush {
+                level = flush_denormal(level);
+            }
+            cursor.g = cursor.g * cursor.mul + cursor.add;
+            *sample = self.map_output(level);
+        }
+        self.end_run(&cursor, len, level);
+        cursor.position += len;
+        self.cursor = Some(cursor);
+        len
+    }
+
+    // jump into a note as if it had started seconds_since_note_on ago, released released_after
+    // seconds after its start or still held when None. the next sample continues from there
+    pub fn seek(&mut self, seconds_since_note_on: f32, released_after: Option<f32>) {
+        assert!(seconds_since_note_on >= 0.0);
+        self.fade = None;
+        self.shot = false;
+        self.restart_pending = false;
+        self.release_pending = false;
+        self.gate_remaining = None;
+        self.retrigger();
+
+        if let Some(r) = released_after {
+            assert!((0.0..=seconds_since_note_on).contains(&r));
+        }
+        let released = released_after.filter(|_| self.mode != EnvelopeMode::OneShot); // one shots ignore the gate
+        let held_samples = (released.unwrap_or(seconds_since_note_on) * self.sample_rate).round() as u64;
+        // a release starts from the last sample before note off
+        self.note_on_duration = if released.is_some() { held_samples.saturating_sub(1) } else { held_samples } * TICKS;
+        if self.mode == EnvelopeMode::SustainLoop {
+            self.wrap_sustain_loop_fully();
+        }
+        let (phase, val) = if released.is_some() {
+            self.last_gate_val = if held_samples > 0 { self.state_at(true, self.note_on_duration).1 } else { 0.0 };
+            self.note_off_duration = ((seconds_since_note_on * self.sample_rate).round() as u64 - held_samples) * TICKS;
+            self.state_at(false, self.note_off_duration)
+        } else {
+            self.state_at(true, self.note_on_duration)
+        };
+        let event = if released_after.is_some() { ADSREvent::NoteOff } else { ADSREvent::NoteOn };
+        self.current_event = event;
+        self.next_event = event;
+        self.current_phase = phase;
+        self.current_val = val;
+    }
+
+    // the sample the next generate() will return, leaving the envelope as it is. it does not
+    // allocate in real-time safe mode
+    pub fn peek(&mut self) -> f32 {
+        self.dry_run(|adsr| adsr.generate())
+    }
+
+    // the rest of a note that ends by itself (a one-shot, or a gate of fixed length with nothing
+    // scheduled after it), down to and including the sample reaching Silence, with its length
+    // known up front. None when the note could be held forever
+    pub fn finite_tail(&mut self) -> Option<impl ExactSizeIterator<Item = f32> + '_> {
+        self.apply_due();
+        let one_shot = self.shot || self.mode == EnvelopeMode::OneShot;
+        let fixed_gate = self.gate_remaining.is_some() && self.sustain_pedal < 1.0 && self.scheduled.is_empty();
+        let silent = !self.is_active() && self.next_event == self.current_event && !self.restart_pending
+            && self.scheduled.is_empty();
+        if self.paused || !(one_shot || fixed_gate || silent) {
+            return None;
+        }
+        let len = self.dry_run(|adsr| {
+            let mut n = 0;
+            if !silent {
+                loop {
+                    adsr.generate();
+                    n += 1;
+                    if !adsr.is_active() {
+                        break;
+                    }
+                }
+            }
+            n
+        });
+        Some(self.fill_next(len))
+    }
+
+    // run f and put the envelope back as it was, without reporting phase changes. the queue
+    // and the slews run on copies in spare buffers, so nothing is allocated once those have room
+    fn dry_run<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        let scheduled = core::mem::take(&mut self.scheduled);
+        let slews = core::mem::take(&mut self.slews);
+        let state = self.save_state();
+        self.spare_scheduled.clone_from(&scheduled);
+        self.spare_slews.clone_from(&slews);
+        self.scheduled = core::mem::take(&mut self.spare_scheduled);
+        self.slews = core::mem::take(&mut self.spare_slews);
+        // shared parameters are copied on write by the slews and put back, unshared ones are
+        // set back to where the slews were
+        let shared = (Arc::strong_count(&self.params) > 1).then(|| Arc::clone(&self.params));
+        let spare_params = self.spare_params.clone();
+        let dropped_events = self.dropped_events;
+        #[cfg(feature = "debug-validate")]
+        let violation = self.violation.take();
+        let on_phase_change = self.on_phase_change.take();
+        #[cfg(feature = "std")]
+        let phase_sender = self.phase_sender.take();
+
+        let result = f(self);
+
+        self.spare_scheduled = core::mem::take(&mut self.scheduled);
+        self.spare_slews = core::mem::take(&mut self.slews);
+        self.restore_state(state);
+        self.scheduled = scheduled;
+        self.slews = slews;
+        match shared {
+            Some(params) => self.params = params,
+            None if !self.slews.is_empty() => {
+                let params = Arc::make_mut(&mut self.params);
+                for slew in self.slews.iter() {
+                    params.set_param((slew.make)(slew.value));
+                }
+            },
+            None => {},
+        }
+        self.spare_params = spare_params;
+        self.dropped_events = dropped_events;
+        #[cfg(feature = "debug-validate")]
+        {
+            self.violation = violation;
+        }
+        self.on_phase_change = on_phase_change;
+        #[cfg(feature = "std")]
+        {
+            self.phase_sender = phase_sender;
+        }
+        result
+    }
+
+    // move n samples ahead without output, e.g. to catch up after a transport jump.
+    // stretches where nothing changes (sustain, silence) are skipped in one step
+    pub fn advance(&mut self, n: u64) {
+        let mut left = n;
+        while left > 0 {
+            if self.is_steady() {
+                let mut skip = left;
+                if let Some(&(at, _)) = self.scheduled.front() {
+                    skip = skip.min(at.saturating_sub(self.clock));
+                }
+                if let Some(remaining) = self.gate_remaining {
+                    skip = skip.min(remaining);
+                }
+                if skip > 0 {
+                    self.clock += skip;
+                    self.gate_remaining = self.gate_remaining.map(|r| r - skip);
+                    left -= skip;
+                    continue;
+                }
+            }
+            self.generate();
+            left -= 1;
+        }
+    }
+
+    // generate() would return the same value without changing anything but the clock
+    fn is_steady(&self) -> bool {
+        if self.paused || self.fade.is_some() || self.bridge.is_some() || self.shot || self.restart_pending
+            || self.next_event != self.current_event {
+            return false;
+        }
+        match self.current_phase {
+            ADSRPhase::Silence => true,
+            ADSRPhase::Sustain => self.current_event == ADSREvent::NoteOn,
+            _ => false,
+        }
+    }
+
+    // up to max samples from now that follow a single curve, None if the next sample needs
+    // generate()
+    pub(crate) fn run(&self, max: usize) -> Option<Cursor> {
+        // the callers apply the output stage as an offset and a gain, which a dB scale is not
+        if self.control_period != 1 || self.level_scale != LevelScale::Linear {
+            return None;
+        }
+        let len = self.steady_samples(max);
+        let constant = |level: f32| Cursor {
+            base: level as Real, scale: 0.0, g: 0.0, mul: 1.0, add: 0.0,
+            length: len, position: 0, phase: self.current_phase, note_on: true, duration: 0, step: 0,
+            evaluated: None,
+        };
+        let note_on = self.mode == EnvelopeMode::OneShot || self.current_event != ADSREvent::NoteOff;
+        match self.current_phase {
+            _ if len == 0 => return None,
+            ADSRPhase::Silence => return Some(constant(0.0)),
+            ADSRPhase::Sustain if note_on => return Some(constant(self.state_at(true, self.note_on_duration).1)),
+            _ if !note_on && self.sustain_pedal != 0.0 => return None,
+            _ => {}
+        }
+        let duration = if note_on { self.note_on_duration } else { self.note_off_duration };
+        let mut cursor = self.stage_cursor(note_on, duration, TICKS)?;
+        if cursor.phase != self.current_phase || cursor.evaluated.is_some() {
+            return None;
+        }
+        cursor.length = cursor.length.min(len);
+        Some(cursor)
+    }
+
+    // up to max samples from now in which generate() only steps the timeline: no events,
+    // glides, fades, loops or phase reports due. 0 if the next sample needs generate()
+    fn steady_samples(&self, max: usize) -> usize {
+        if cfg!(feature = "debug-validate") {
+            return 0; // every sample goes through generate() to be checked
+        }
+        if self.paused || self.fade.is_some() || self.bridge.is_some()
+            || self.param_fade.is_some() || !self.slews.is_empty()
+            || self.gate_remaining.is_some() || self.shot || self.restart_pending || self.next_event != self.current_event
+            || self.mode == EnvelopeMode::SustainLoop || self.reported_phase != self.current_phase {
+            return 0;
+        }
+        match self.scheduled.front() {
+            Some(&(at, _)) => max.min(at.saturating_sub(self.clock) as usize),
+            None => max,
+        }
+    }
+
+    // the state after the first len samples of run, the last of them being level
+    pub(crate) fn end_run(&mut self, run: &Cursor, len: usize, level: f32) {
+        self.clock += len as u64;
+        if run.note_on {
+            self.note_on_duration += run.step * len as u64;
+        } else {
+            self.note_off_duration += run.step * len as u64;
+        }
+        self.current_val = level;
+    }
+
+    // offset and gain of the output stage
+    pub(crate) fn output_map(&self) -> (f32, f32) {
+        let offset = self.map_output(0.0);
+        (offset, self.map_output(1.0) - offset)
+    }
+
+    // the next n samples, generated lazily as the iterator is consumed
+    pub fn fill_next(&mut self, n: usize) -> impl ExactSizeIterator<Item = f32> + '_ {
+        (0..n).map(move |_| self.generate())
+    }
+
+    // duration_secs worth of samples from the current state, events timed in seconds from now.
+    // events at or after the end are ignored
+    pub fn render(&mut self, events: &[(f64, ADSREvent)], duration_secs: f64) -> Vec<f32> {
+        assert!(duration_secs >= 0.0);
+        let sample_rate = self.sample_rate as f64;
+        let len = (duration_secs * sample_rate).round() as u64;
+        for &(t, event) in events {
+            assert!(t >= 0.0);
+            let at = (t * sample_rate).round() as u64;
+            if at < len {
+                self.schedule_event(self.clock + at, event);
+            }
+        }
+        (0..len).map(|_| self.generate()).collect()
+    }
+
+    // multiply the envelope into audio in place, like a VCA
+    pub fn apply(&mut self, buf: &mut [f32]) {
+        for sample in buf.iter_mut() {
+            *sample *= self.generate();
+        }
+    }
+
+    // interleaved frames of channels samples, every sample of a frame gets the same gain
+    pub fn apply_interleaved(&mut self, buf: &mut [f32], channels: usize) {
+        if self.realtime && channels == 0 {
+            return;
+        }
+        assert!(channels > 0 && (self.realtime || buf.len().is_multiple_of(channels)));
+        for frame in buf.chunks_exact_mut(channels) {
+            let gain = self.generate();
+            for sample in frame.iter_mut() {
+                *sample *= gain;
+            }
+        }
+    }
+
+    // like fill_block, applying each event before the sample at its offset. the events are
+    // scheduled at those samples, so the output is the same however a timeline is cut into
+    // blocks. events are sorted by offset, offsets past the end of the block are ignored
+    pub fn process_block(&mut self, out: &mut [f32], events: &[(usize, ADSREvent)]) {
+        assert!(self.realtime || events.windows(2).all(|w| w[0].0 <= w[1].0));
+        let mut start = 0;
+        for &(offset, event) in events {
+            let offset = offset.max(start); // an unsorted one applies where the block is
+            if offset < out.len() {
+                self.schedule_event(self.clock + offset as u64, event);
+            }
+            start = offset;
+        }
+        self.fill_block(out);
+    }
+
+    fn generate_fade(&mut self) -> Option<f32> {
+        let fade = self.fade.as_mut()?;
+        fade.position += 1;
+        let val = if fade.position >= fade.samples {
+            self.fade = None;
+            self.current_phase = ADSRPhase::Silence;
+            0.0
+        } else {
+            fade.from * (1.0 - fade.position as f32 / fade.samples as f32)
+        };
+        self.current_val = val;
+        Some(val)
+    }
+
+    fn generate_bridge(&mut self, val: f32) -> f32 {
+        if self.current_event == ADSREvent::NoteOff {
+            self.bridge = None; // released before the glide finished, the release takes over
+        }
+        let Some(bridge) = self.bridge.as_mut() else {
+            return val;
+        };
+        bridge.position += 1;
+        let x = bridge.position as f32 / bridge.samples as f32;
+        let val = bridge.from + (val - bridge.from) * x;
+        if bridge.position >= bridge.samples {
+            self.bridge = None;
+        }
+        self.current_val = val;
+        val
+    }
+
+    // output stage, applied after the envelope math
+    fn map_output(&self, val: f32) -> f32 {
+        let val = self.amplitude(val.clamp(0.0, 1.0)); // whatever rounding in the curve math did to the ends
+        let val = if self.inverted { 1.0 - val } else { val };
+        let val = match self.polarity {
+            Polarity::Unipolar => val * self.depth,
+            Polarity::Bipolar => (2.0 * val - 1.0) * self.depth,
+        };
+        let (min, max) = self.output_range;
+        min + (max - min) * val
+    }
+
+    fn generate_one_shot(&mut self) -> f32 {
+        if self.next_event == ADSREvent::NoteOn && (self.current_event == ADSREvent::NoteOff || self.restart_pending) {
+            self.restart_pending = false;
+            self.retrigger(); // fire the shot
+        }
+        self.current_event = self.next_event;
+
+        if self.current_phase == ADSRPhase::Silence {
+            self.current_val = 0.0;
+            return 0.0;
+        }
+
+        // the gate is ignored once fired, so the note on timeline runs to the end
+        let (next_phase, next_val) = self.next_state(ADSREvent::NoteOn);
+
+        self.note_on_duration += TICKS;
+
+        if self.shot && next_phase == ADSRPhase::Silence {
+            self.shot = false;
+            self.next_event = ADSREvent::NoteOff;
+            self.current_event = ADSREvent::NoteOff;
+        }
+
+        self.current_phase = next_phase;
+        self.current_val   = next_val;
+        next_val
+    }
+
+    fn generate_gated(&mut self) -> f32 {
+        match self.next_event {
+            ADSREvent::NoteOn | ADSREvent::NoteOnWithVelocity(_) | ADSREvent::Retrigger => {
+                if self.current_event == ADSREvent::NoteOff || self.restart_pending {
+                    self.restart_pending = false;
+                    self.start_note();
+                }
+
+                let (next_phase, next_val) = self.next_state(self.next_event);
+
+                if self.current_phase != ADSRPhase::Sustain && next_phase != ADSRPhase::Silence {
+                    self.note_on_duration += TICKS;
+                }
+
+                if self.mode == EnvelopeMode::SustainLoop {
+                    self.wrap_sustain_loop();
+                }
+
+                self.current_event = self.next_event;
+                self.current_phase = next_phase;
+                self.current_val   = next_val;
+                next_val
+            },
+            ADSREvent::NoteOff => {
+                if self.current_event == ADSREvent::NoteOn {
+                    self.last_gate_val = self.current_val; // remember last sample value before note off
+                    self.current_event = ADSREvent::NoteOff;
+                }
+                if self.current_phase == ADSRPhase::Silence {
+                    return 0.0; // nothing left to release
+                }
+
+                let (next_phase, next_val) = self.next_state(self.next_event);
+
+                if self.current_phase != ADSRPhase::Silence {
+                    self.note_off_duration += self.note_off_step();
+                }
+
+                self.current_event = self.next_event;
+                self.current_phase = next_phase;
+                self.current_val   = next_val;
+                next_val
+            }
+        }
+    }
+
+    // jump back to loop start once the note on timeline reaches the end of decay
+    fn wrap_sustain_loop(&mut self) {
+        let span = self.attack_time() + self.hold_time() + self.decay_time();
+        let loop_end = ((self.delay_time() + span) * self.sample_rate).round() as u64 * TICKS;
+        let loop_start = ((self.delay_time() + (self.params.loop_start * self.time_scale).min(span)) * self.sample_rate).round() as u64 * TICKS;
+        let exhausted = match self.loop_count {
+            LoopCount::Infinite => false,
+            LoopCount::Times(n) => self.loops_done >= n,
+        };
+        if loop_end > loop_start && self.note_on_duration >= loop_end && !exhausted {
+            self.note_on_duration -= loop_end - loop_start;
+            self.loops_done += 1;
+        }
+    }
+
+    fn wrap_sustain_loop_fully(&mut self) {
+        loop {
+            let before = self.note_on_duration;
+            self.wrap_sustain_loop();
+            if self.note_on_duration == before {
+                break;
+            }
+        }
+    }
+
+    fn retrigger(&mut self) {
+        self.velocity = self.next_velocity;
+        self.key = self.next_key;
+        self.bridge = None;
+        self.attack_origin = None;
+        self.cursor = None;
+        self.current_phase = ADSRPhase::Delay; // the note on timeline starts over
+        self.note_on_duration  = 0;
+        self.note_off_duration = 0;
+        self.loops_done = 0;
+        self.end_param_fade(); // the new note starts with the new parameters
+        if self.humanize_time > 0.0 || self.humanize_level > 0.0 {
+            for i in 0..7 {
+                let amount = if i < 5 { self.humanize_time } else { self.humanize_level };
+                self.jitter[i] = 1.0 + amount * self.next_random();
+            }
+        }
+    }
+
+    // uniform in -1..1, splitmix64
+    fn next_random(&mut self) -> f32 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 40) as f32 / (1 << 23) as f32 - 1.0
+    }
+
+    fn start_note(&mut self) {
+        let from = self.current_val;
+        if self.current_phase == ADSRPhase::Silence {
+            self.retrigger();
+            return;
+        }
+        match self.retrigger_mode {
+            RetriggerMode::HardReset => {
+                self.retrigger();
+            },
+            RetriggerMode::FromCurrentValue => {
+                self.retrigger();
+                self.attack_origin = Some(from);
+            },
+            RetriggerMode::Legato => {
+                self.note_off_duration = 0; // pick the note on timeline up where it stopped
+                self.glide_from(from);
+            }
+        }
+    }
+
+    fn glide_from(&mut self, from: f32) {
+        let samples = (self.params.attack_time * self.time_scale * self.sample_rate).round() as u32;
+        self.bridge = if samples > 0 {
+            Some(Bridge { from, samples, position: 0 })
+        } else {
+            None
+        };
+    }
+
+    // level the stage entered starts from minus the one the stage before ends at, for each
+    // boundary of a note with the current parameters: attack to hold, hold to decay, decay to
+    // sustain (or silence), sustain to release and release to silence. all 0.0, an invariant
+    // for tests to check; only the attack may jump, from silence to the start level
+    pub fn boundary_jumps(&self) -> [(ADSRPhase, f32); 5] {
+        let p = &self.params;
+        let [_, attack, hold, decay, rest] = self.note_on_stages();
+        let release = Segment::new(ADSRPhase::Release, rest.target(), 0.0, self.release_time(), p.release_shape())
+            .fast_math(p.fast_math);
+        let end = |stage: &Segment| stage.level(stage.duration());
+        let start = |stage: &Segment| stage.level(0.0);
+        [
+            (ADSRPhase::Hold, start(&hold) - end(&attack)),
+            (ADSRPhase::Decay, start(&decay) - end(&hold)),
+            (rest.phase(), start(&rest) - end(&decay)),
+            (ADSRPhase::Release, start(&release) - end(&rest)),
+            (ADSRPhase::Silence, -end(&release)),
+        ]
+    }
+
+    // stages of the note on timeline, measured from note on
+    fn note_on_stages(&self) -> [Segment<'_>; 5] {
+        let p = &self.params;
+        let peak = self.peak_level();
+        let floor = self.decay_level();
+        let origin = self.attack_origin.unwrap_or(self.scaled(p.start_level));
+        let (hold_time, decay_time) = if self.mode == EnvelopeMode::ASR {
+            (0.0, 0.0)
+        } else {
+            (self.hold_time(), self.decay_time())
+        };
+        let rest = if self.decays_to_silence() {
+            Segment::flat(ADSRPhase::Silence, 0.0, f32::INFINITY)
+        } else {
+            Segment::flat(ADSRPhase::Sustain, floor, f32::INFINITY)
+        };
+        [
+            Segment::flat(ADSRPhase::Delay, self.attack_origin.unwrap_or(0.0), self.delay_time()),
+            Segment::new(ADSRPhase::Attack, origin, peak, self.attack_time(), p.attack_shape()).fast_math(p.fast_math),
+            Segment::flat(ADSRPhase::Hold, peak, hold_time),
+            Segment::new(ADSRPhase::Decay, peak, floor, decay_time, p.decay_shape()).fast_math(p.fast_math),
+            rest,
+        ]
+    }
+
+    // stages of the note off timeline, measured from note off
+    fn note_off_stages(&self) -> [Segment<'_>; 2] {
+        let p = &self.params;
+        [
+            Segment::new(ADSRPhase::Release, self.last_gate_val, 0.0, self.release_time(), p.release_shape())
+                .fast_math(p.fast_math),
+            Segment::flat(ADSRPhase::Silence, 0.0, f32::INFINITY),
+        ]
+    }
+
+    // the next phase and level, release tails flushed to zero once too quiet to matter
+    fn next_state(&mut self, next_event: ADSREvent) -> (ADSRPhase, f32) {
+        match self.faded_state(next_event) {
+            (phase @ (ADSRPhase::Release | ADSRPhase::Silence), val) => (phase, flush_denormal(val)),
+            state => state,
+        }
+    }
+
+    // crossfaded from the trajectory of the parameters replaced during the note, if any
+    fn faded_state(&mut self, next_event: ADSREvent) -> (ADSRPhase, f32) {
+        let (phase, val) = self.follow_timeline(next_event);
+        let Some(fade) = self.param_fade.as_mut() else {
+            return (phase, val);
+        };
+        fade.position += 1;
+        let x = fade.position as f32 / fade.samples as f32;
+        let old = Arc::clone(&fade.old);
+        if fade.position >= fade.samples {
+            self.end_param_fade();
+        }
+        let note_on = next_event != ADSREvent::NoteOff;
+        let duration = if note_on { self.note_on_duration } else { self.note_off_duration };
+        let new = core::mem::replace(&mut self.params, old);
+        let (_, from) = self.state_at(note_on, duration);
+        self.params = new;
+        (phase, from + (val - from) * x)
+    }
+
+    fn follow_timeline(&mut self, next_event: ADSREvent) -> (ADSRPhase, f32) {
+        let note_on = next_event != ADSREvent::NoteOff;
+        let (duration, step) = if note_on {
+            (self.note_on_duration, TICKS)
+        } else {
+            (self.note_off_duration, self.note_off_step())
+        };
+
+        // keep stepping while the timeline runs on as it did when the cursor was laid out,
+        // anything else (a new note, a loop, a pedal change) lays out a new one right away
+        let cursor = match self.cursor.take() {
+            Some(cursor) if cursor.note_on == note_on && cursor.position < cursor.length
+                && duration == cursor.duration + cursor.position as u64 * cursor.step => Some(cursor),
+            _ if self.control_period == 1 => self.stage_cursor(note_on, duration, step),
+            _ => Some(self.control_cursor(note_on, duration, step)),
+        };
+        let Some(mut cursor) = cursor else {
+            return self.state_at(note_on, duration);
+        };
+        let val = match cursor.evaluated {
+            Some(index) => {
+                let t = self.seconds(duration) - cursor.g; // as locate() would
+                if note_on {
+                    self.note_on_stages()[index].level_real(t)
+                } else {
+                    self.note_off_stages()[index].level_real(t)
+                }
+            },
+            None => {
+                let val = cursor.base + cursor.scale * cursor.g;
+                cursor.g = cursor.g * cursor.mul + cursor.add;
+                val
+            },
+        };
+        cursor.position += 1;
+        self.cursor = Some(cursor);
+        (cursor.phase, to_f32(val))
+    }
+
+    // the stage duration samples into a timeline, up to CURSOR_SPAN samples but never past its
+    // end. None once the timeline is over
+    fn stage_cursor(&self, note_on: bool, duration: u64, step: u64) -> Option<Cursor> {
+        let speed = Self::samples(step);
+        let cursor = |index: usize, stages: &[Segment], t: Real| {
+            let stage = &stages[index];
+            if stage.duration().is_infinite() {
+                return Some(Cursor {
+                    base: stage.target() as Real, scale: 0.0, g: 0.0, mul: 1.0, add: 0.0,
+                    length: CURSOR_SPAN, position: 0, phase: stage.phase(), note_on, duration, step,
+                    evaluated: None,
+                });
+            }
+            let left = to_f32((stage.duration() as Real - t) * self.sample_rate as Real);
+            let length = if step > 0 {
+                (left / speed - 1e-3).ceil() as usize // forgive rounding of the stage times
+            } else {
+                CURSOR_SPAN
+            };
+            if length == 0 {
+                return None;
+            }
+            let (from, to) = (stage.from() as Real, stage.target() as Real);
+            let mut cursor = Cursor {
+                base: to, scale: 0.0, g: 0.0, mul: 1.0, add: 0.0,
+                length: length.min(CURSOR_SPAN), position: 0, phase: stage.phase(), note_on, duration, step,
+                evaluated: None,
+            };
+            if from == to {
+                return Some(cursor);
+            }
+            let dx = speed as Real / (stage.duration() as Real * self.sample_rate as Real);
+            let x = (t / stage.duration() as Real).clamp(0.0, 1.0);
+            let (x, dx) = if to >= from { (x, dx) } else { (1.0 - x, -dx) };
+            cursor.base = from.min(to);
+            cursor.scale = (to - from).abs();
+            let mul_m1 = match *stage.curve() {
+                CurveKind::Linear | CurveKind::Factor(0.0) => {
+                    cursor.g = x;
+                    cursor.add = dx;
+                    0.0
+                },
+                CurveKind::Factor(c) => {
+                    // (b^(2x) - 1) / (b^2 - 1) as in curve_function, with g = b^(2x) - 1
+                    let k = 2.0 * Self::curve_base(c as Real).ln();
+                    cursor.scale /= k.exp_m1();
+                    cursor.g = (k * x).exp_m1();
+                    cursor.mul = (k * dx).exp();
+                    cursor.add = (k * dx).exp_m1();
+                    cursor.add
+                },
+                _ => {
+                    cursor.g = stages[..index].iter().map(|stage| stage.duration() as Real).sum();
+                    cursor.length = length;
+                    cursor.evaluated = Some(index);
+                    return Some(cursor);
+                },
+            };
+            // a pole above 1 moves away from its target and would blow up the rounding errors,
+            // those curves stay anchored
+            if self.one_pole && cursor.mul <= 1.0 {
+                cursor.add = cursor.scale * cursor.add - cursor.base * mul_m1;
+                cursor.g = cursor.base + cursor.scale * cursor.g;
+                cursor.base = 0.0;
+                cursor.scale = 1.0;
+                cursor.length = length;
+            }
+            Some(cursor)
+        };
+        let t = self.seconds(duration);
+        if note_on {
+            let stages = self.note_on_stages();
+            locate_index(&stages, t).and_then(|(i, t)| cursor(i, &stages, t))
+        } else {
+            let stages = self.note_off_stages();
+            locate_index(&stages, t).and_then(|(i, t)| cursor(i, &stages, t))
+        }
+    }
+
+    // straight line to the level control_period samples ahead. lines end at stage boundaries so
+    // corners such as the peak stay exact
+    fn control_cursor(&self, note_on: bool, duration: u64, step: u64) -> Cursor {
+        let period = self.control_period as usize;
+        let (_, left) = self.phase_at(note_on, duration);
+        let length = if step > 0 {
+            ((left / Self::samples(step) - 1e-3).ceil() as usize).clamp(1, period) // forgive rounding of the stage times
+        } else {
+            period
+        };
+        let (phase, from) = self.state_at(note_on, duration);
+        let (_, to) = self.state_at(note_on, duration + length as u64 * step);
+        Cursor {
+            base: from as Real, scale: (to - from) as Real, g: 0.0, mul: 1.0, add: 1.0 / length as Real,
+            length, position: 0, phase, note_on, duration, step,
+            evaluated: None,
+        }
+    }
+
+    // ticks to seconds without a division
+    fn seconds(&self, ticks: u64) -> Real {
+        (ticks as f64 / TICKS as f64 * self.sample_period) as Real
+    }
+
+    fn samples(ticks: u64) -> f32 {
+        (ticks as f64 / TICKS as f64) as f32
+    }
+
+    // the note off timeline slows down as far as the sustain pedal is pressed
+    fn note_off_step(&self) -> u64 {
+        ((1.0 - self.sustain_pedal) * TICKS as f32).round() as u64
+    }
+
+    // phase and level duration samples into the note on or note off timeline
+    fn state_at(&self, note_on: bool, duration: u64) -> (ADSRPhase, f32) {
+        let t = self.seconds(duration);
+        let level = |stages: &[Segment]| {
+            locate_index(stages, t).map(|(i, t)| (stages[i].phase(), to_f32(stages[i].level_real(t))))
+        };
+        let located = if note_on {
+            level(&self.note_on_stages())
+        } else {
+            level(&self.note_off_stages())
+        };
+        // the last stage of each timeline never completes
+        located.unwrap_or((ADSRPhase::Silence, 0.0))
+    }
+
+    // like state_at without evaluating the curve, along with the samples left in the stage
+    fn phase_at(&self, note_on: bool, duration: u64) -> (ADSRPhase, f32) {
+        let t = self.seconds(duration);
+        let left = |stages: &[Segment]| {
+            locate_index(stages, t).map(|(i, t)| {
+                (stages[i].phase(), to_f32((stages[i].duration() as Real - t) * self.sample_rate as Real))
+            })
+        };
+        let located = if note_on {
+            left(&self.note_on_stages())
+        } else {
+            left(&self.note_off_stages())
+        };
+        located.unwrap_or((ADSRPhase::Silence, f32::INFINITY))
+    }
+
+    // an amplitude on the scale the stages run on
+    fn scaled(&self, amplitude: f32) -> f32 {
+        match self.level_scale {
+            LevelScale::Linear => amplitude,
+            LevelScale::Decibels(range) if amplitude > 0.0 => (1.0 + 20.0 * amplitude.log10() / range).max(0.0),
+            LevelScale::Decibels(_) => 0.0,
+        }
+    }
+
+    // the amplitude of a level the stages produced, silence at the bottom of the dB range
+    fn amplitude(&self, level: f32) -> f32 {
+        match self.level_scale {
+            LevelScale::Linear => level,
+            LevelScale::Decibels(range) if level > 0.0 => 10f32.powf((level - 1.0) * range / 20.0),
+            LevelScale::Decibels(_) => 0.0,
+        }
+    }
+
+    // level reached at the end of attack and kept during hold
+    fn peak_level(&self) -> f32 {
+        self.scaled(if self.params.decay_time > 0.0 || self.params.always_peak || self.decays_to_silence() || self.mode == EnvelopeMode::ASR {
+            (self.params.peak_level * self.velocity_scale(self.params.velocity_to_peak) * self.jitter[5]).min(1.0)
+        } else {
+            self.sustain_level()
+        })
+    }
+
+    // level the decay falls to
+    fn decay_level(&self) -> f32 {
+        if self.decays_to_silence() {
+            0.0
+        } else if self.mode == EnvelopeMode::ASR {
+            self.peak_level()
+        } else {
+            self.scaled(self.sustain_level())
+        }
+    }
+
+    // an attack starting part way up only covers the rest of the distance to the peak
+    fn attack_time(&self) -> f32 {
+        let p = &self.params;
+        let attack_time = (p.attack_time * self.time_scale * self.jitter[1]).max(self.min_ramp);
+        let Some(origin) = self.attack_origin else {
+            return attack_time;
+        };
+        let peak = self.peak_level();
+        let span = (peak - self.scaled(p.start_level)).abs();
+        if span > 0.0 {
+            attack_time * ((peak - origin).abs() / span).min(1.0)
+        } else {
+            attack_time
+        }
+    }
+
+    fn delay_time(&self) -> f32 {
+        self.params.delay_time * self.time_scale * self.jitter[0]
+    }
+
+    fn hold_time(&self) -> f32 {
+        self.params.hold_time * self.time_scale * self.jitter[2]
+    }
+
+    fn decay_time(&self) -> f32 {
+        if self.params.decay_time == 0.0 && self.params.always_peak {
+            return 1.0 / self.sample_rate; // one sample at the peak
+        }
+        self.params.decay_time * self.key_scale() * self.time_scale * self.jitter[3]
+    }
+
+    fn release_time(&self) -> f32 {
+        (self.params.release_time * self.key_scale() * self.time_scale * self.jitter[4]).max(self.min_ramp)
+    }
+
+    // stage time factor for the sounding key relative to middle C
+    fn key_scale(&self) -> f32 {
+        2.0_f32.powf(-self.params.key_tracking * (self.key as f32 - 60.0) / 12.0)
+    }
+
+    fn sustain_level(&self) -> f32 {
+        (self.params.sustain_level * self.velocity_scale(self.params.velocity_to_sustain) * self.jitter[6]).min(1.0)
+    }
+
+    // 1.0 at full velocity, down to 1.0 - amount at zero velocity
+    fn velocity_scale(&self, amount: f32) -> f32 {
+        1.0 - amount * (1.0 - self.params.velocity_curve.apply(self.velocity))
+    }
+
+    fn decays_to_silence(&self) -> bool {
+        self.shot || matches!(self.mode, EnvelopeMode::OneShot | EnvelopeMode::Percussive)
+    }
+
+    // exponential curve that passes (0, 0) and (w, h). runs every sample, so out of range
+    // arguments are clamped rather than asserted
+    fn curve_function<T: Float>(x: T, h: T, w: T, curve_factor: T, fast: bool) -> T {
+        let (one, two) = (T::one(), cast::<T>(2.0));
+        let x = x.clamp(T::zero(), w);
+        let curve_factor = curve_factor.clamp(-one, one);
+        if curve_factor == T::zero() { // linear
             h / w * x
+        } else if fast {
+            // (2^(2x/w * log2 b) - 1) / (2^(2 log2 b) - 1), the same curve
+            let l = fast_math::log2(cast(Self::curve_base(curve_factor)));
+            h * cast::<T>(fast_math::exp2_m1(cast::<f32>(two * x / w) * l) / fast_math::exp2_m1(2.0 * l))
+        } else {
+            let b = Self::curve_base(curve_factor);
+            h*(b.powf(two*x/w)-one)/(b.powf(two)-one)
+        }
+    }
+
+    // base of the power in curve_function
+    fn curve_base<T: Float>(curve_factor: T) -> T {
+        let eps = cast::<T>(0.005);
+        let half = cast::<T>(0.5);
+        let r = -curve_factor * (half - eps) + half; // -1.0..1.0 -> 1.0-eps..0.0+eps
+        T::one()/r-T::one()
+    }
+}
+
+// a voice in the same state that produces the same samples from here on. callbacks
+// and phase senders are not carried over, the clone reports nothing until given its own
+impl Clone for ADSR {
+    fn clone(&self) -> Self {
+        let mut adsr = ADSR::with_shared_params(Arc::clone(&self.params), self.sample_rate);
+        adsr.mode = self.mode;
+        adsr.polarity = self.polarity;
+        adsr.level_scale = self.level_scale;
+        adsr.output_range = self.output_range;
+        adsr.depth = self.depth;
+        adsr.inverted = self.inverted;
+        adsr.loop_count = self.loop_count;
+        adsr.retrigger_mode = self.retrigger_mode;
+        adsr.param_policy = self.param_policy;
+        adsr.control_period = self.control_period;
+        adsr.one_pole = self.one_pole;
+        adsr.time_scale = self.time_scale;
+        adsr.min_ramp = self.min_ramp;
+        adsr.bpm = self.bpm;
+        adsr.synced_beats = self.synced_beats;
+        adsr.humanize_time = self.humanize_time;
+        adsr.humanize_level = self.humanize_level;
+        adsr.seed = self.seed;
+        adsr.param_fade_samples = self.param_fade_samples;
+        adsr.smoothing_samples = self.smoothing_samples;
+        adsr.slews = self.slews.clone();
+        adsr.restore_state(self.save_state());
+        adsr.set_realtime_safe(self.realtime);
+        adsr
+    }
+}
+
+#[cfg(feature = "dasp")]
+impl Signal for ADSR {
+    type Frame = f32;
+
+    fn next(&mut self) -> Self::Frame {
+        self.generate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plotters::prelude::*;
+    use ADSREvent::*;
+    use ADSRParamKind::*;
+    use alloc::string::ToString;
+
+    // what Signal::next() does for the envelope, so the tests also run without dasp
+    #[cfg(not(feature = "dasp"))]
+    trait Next {
+        fn next(&mut self) -> f32;
+    }
+
+    #[cfg(not(feature = "dasp"))]
+    impl Next for ADSR {
+        fn next(&mut self) -> f32 {
+            self.generate()
+        }
+    }
+
+    // samples that come out exact in f32 but a hair off with the f64 feature, the stage times
+    // being rounded to f32 before the math is done
+    fn assert_samples(out: &[f32], expected: &[f32]) {
+        if cfg!(feature = "f64") {
+            assert!(out.len() == expected.len() && out.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-6));
         } else {
-            const EPS: f32 = 0.005;
-            let r = -curve_factor * (0.5 - EPS) + 0.5; // -1.0..1.0 -> 1.0-eps..0.0+eps
-            h*((1.0/r-1.0).powf(2.0*x/w)-1.0)/((1.0/r-1.0).powf(2.0)-1.0)
+            assert_eq!(out, expected);
+        }
+    }
+
+    fn create_chart(filename: &str, cap: &str, adsr: &mut ADSR, t_sec: f32, events: &mut VecDeque<(f32, ADSREvent)>) {
+        let data_len: usize = (adsr.sample_rate * t_sec) as usize;
+        while let Some((t, event)) = events.pop_back() {
+            adsr.schedule_event((t * adsr.sample_rate).round() as u64, event);
+        }
+        let adsr_vec: Vec<f32> = (0..=data_len).map(|_| adsr.next()).collect();
+
+        let root = BitMapBackend::new(filename, (1024, 768)).into_drawing_area();
+
+        root.fill(&WHITE).unwrap();
+
+        let mut chart = ChartBuilder::on(&root)
+            .set_label_area_size(LabelAreaPosition::Left, 60)
+            .set_label_area_size(LabelAreaPosition::Bottom, 60)
+            .caption(cap, ("sans-serif", 40))
+            .build_cartesian_2d(-0.5_f32..t_sec+1.0, -0.1_f32..1.1_f32)
+            .unwrap();
+
+        chart
+            .configure_mesh()
+            //.disable_x_mesh()
+            //.disable_y_mesh()
+            .draw()
+            .unwrap();
+
+        chart.draw_series(
+            AreaSeries::new(
+                (0..=data_len).zip(adsr_vec.iter()).map(|(x, y)| (x as f32 / adsr.sample_rate, *y)),
+                0.0,
+                RED.mix(0.2),
+            )
+            .border_style(RED),
+        ).unwrap();
+
+        // To avoid the IO failure being ignored silently, we manually call the present function
+        root.present().unwrap();
+    }
+
+    // charts what render() gives for the events and returns it, for tests checking the values
+    fn render_chart(filename: &str, cap: &str, adsr: &mut ADSR, t_sec: f64, events: &[(f64, ADSREvent)]) -> Vec<f32> {
+        let out = adsr.render(events, t_sec);
+        let period = 1.0 / adsr.sample_rate;
+
+        let root = BitMapBackend::new(filename, (1024, 768)).into_drawing_area();
+        root.fill(&WHITE).unwrap();
+        let mut chart = ChartBuilder::on(&root)
+            .set_label_area_size(LabelAreaPosition::Left, 60)
+            .set_label_area_size(LabelAreaPosition::Bottom, 60)
+            .caption(cap, ("sans-serif", 40))
+            .build_cartesian_2d(-0.5_f32..t_sec as f32 + 1.0, -0.1_f32..1.1_f32)
+            .unwrap();
+        chart.configure_mesh().draw().unwrap();
+        chart.draw_series(
+            AreaSeries::new(
+                out.iter().enumerate().map(|(x, y)| (x as f32 * period, *y)),
+                0.0,
+                RED.mix(0.2),
+            )
+            .border_style(RED),
+        ).unwrap();
+        root.present().unwrap();
+        out
+    }
+
+    #[test]
+    fn silence() {
+        let mut event_queue: VecDeque<(f32, ADSREvent)> = VecDeque::new();
+        let mut adsr = ADSR::new(0.1, 0.1, 0.1, 0.1, 100.0);
+        create_chart("chart/silence.png", "silence", &mut adsr, 2.0, &mut event_queue)
+    }
+
+    #[test]
+    fn silence_although_note_on() {
+        let mut event_queue = VecDeque::new();
+        event_queue.push_front((0.1, NoteOn));
+        let mut adsr = ADSR::new(0.0, 0.0, 0.0, 0.0, 100.0);
+        create_chart("chart/silence_although_note_on.png", "silence_although_note_on", &mut adsr, 2.0, &mut event_queue);
+    }
+
+    #[test]
+    fn just_sustain() {
+        let mut event_queue = VecDeque::new();
+        event_queue.push_front((0.0, NoteOn));
+        let mut adsr = ADSR::new(0.0, 0.0, 1.0, 0.0, 100.0);
+        create_chart("chart/just_sustain.png", "just_sustain", &mut adsr, 2.0, &mut event_queue);
+    }
+
+    #[test]
+    fn typical() {
+        let mut event_queue = VecDeque::new();
+        event_queue.push_front((0.0, NoteOn));
+        event_queue.push_front((1.0, NoteOff));
+        let mut adsr = ADSR::new(0.2, 0.2, 0.8, 1.0, 100.0);
+        adsr.set_param(AttackCurve(-0.5));
+        adsr.set_param(DecayCurve(0.4));
+        adsr.set_param(ReleaseCurve(0.6));
+        create_chart("chart/typical.png", "typical", &mut adsr, 2.0, &mut event_queue);
+    }
+
+    #[test]
+    fn fade_in_out() {
+        let mut event_queue = VecDeque::new();
+        event_queue.push_front((0.0, NoteOn));
+        event_queue.push_front((1.5, NoteOff));
+        let mut adsr = ADSR::new(0.5, 0.0, 0.8, 0.5, 100.0);
+        create_chart("chart/fade_in_out.png", "fade_in_out", &mut adsr, 2.0, &mut event_queue);
+    }
+
+    #[test]
+    fn curvature_edge_case() {
+        let mut event_queue = VecDeque::new();
+        event_queue.push_front((0.0, NoteOn));
+        event_queue.push_front((1.5, NoteOff));
+        let mut adsr = ADSR::new(0.5, 0.5, 0.5, 0.5, 100.0);
+        adsr.set_param(AttackCurve(-1.0));
+        adsr.set_param(DecayCurve(0.0));
+        adsr.set_param(ReleaseCurve(1.0));
+        create_chart("chart/curvature_edge_case.png", "curvature_edge_case", &mut adsr, 2.0, &mut event_queue);
+    }
+
+    #[test]
+    fn hold() {
+        let mut adsr = ADSR::new(0.2, 0.4, 0.5, 0.3, 100.0);
+        adsr.set_param(HoldTime(0.5));
+        adsr.set_param(DecayCurve(0.5));
+        let out = render_chart("chart/hold.png", "hold", &mut adsr, 2.0, &[(0.0, NoteOn), (1.5, NoteOff)]);
+        // the peak is held for 50 samples from the end of the 20 sample attack, the decay follows
+        assert!(out[19] < 1.0);
+        assert!(out[20..=70].iter().all(|&v| v == 1.0));
+        assert!(out[71] < 1.0);
+        assert_samples(&out[110..=150], &[0.5; 41]);
+        assert_eq!(out[180], 0.0);
+    }
+
+    #[test]
+    fn delay() {
+        let mut adsr = ADSR::new(0.2, 0.2, 0.6, 0.3, 100.0);
+        adsr.set_param(DelayTime(0.4));
+        adsr.set_param(HoldTime(0.2));
+        let out = render_chart("chart/delay.png", "delay", &mut adsr, 2.0, &[(0.0, NoteOn), (1.5, NoteOff)]);
+        // silent for the 40 samples of delay, the attack and hold follow 40 samples late
+        assert!(out[..=40].iter().all(|&v| v == 0.0));
+        assert!(out[41] > 0.0);
+        assert!(out[59] < 1.0);
+        assert_samples(&out[60..=80], &[1.0; 21]);
+        assert!(out[81] < 1.0);
+        assert_samples(&out[100..=150], &[0.6; 51]);
+    }
+
+    #[test]
+    fn one_shot() {
+        let mut adsr = ADSR::new(0.05, 0.5, 0.5, 0.5, 100.0);
+        adsr.set_mode(EnvelopeMode::OneShot);
+        adsr.set_param(DecayCurve(0.6));
+        let out = render_chart("chart/one_shot.png", "one_shot", &mut adsr, 2.0, &[(0.0, NoteOn), (0.1, NoteOff), (1.0, NoteOn)]);
+        // the note off at sample 10 changes nothing, the decay goes on past sustain down to zero
+        assert!((out[5] - 1.0).abs() < 1e-6 && (out[105] - 1.0).abs() < 1e-6);
+        assert!(out[10..55].windows(2).all(|w| w[1] < w[0]) && out[54] < 0.5);
+        assert!(out[55..=100].iter().chain(&out[155..]).all(|&v| v == 0.0));
+    }
+
+    #[test]
+    fn percussive() {
+        let mut adsr = ADSR::new(0.05, 0.6, 0.5, 0.2, 100.0);
+        adsr.set_mode(EnvelopeMode::Percussive);
+        adsr.set_param(DecayCurve(0.5));
+        let events = [(0.0, NoteOn), (0.9, NoteOff), (1.0, NoteOn), (1.3, NoteOff)];
+        let out = render_chart("chart/percussive.png", "percussive", &mut adsr, 2.0, &events);
+        // the first note decays past sustain to zero while held, the second is released 35 samples
+        // into its 65 sample attack and decay and is silent 20 samples later
+        assert!((out[5] - 1.0).abs() < 1e-6 && (out[105] - 1.0).abs() < 1e-6);
+        assert!(out[64] > 0.0 && out[65..=100].iter().all(|&v| v < 1e-6));
+        assert!(out[130] > 0.0 && out[149] > 0.0);
+        assert!(out[150..].iter().all(|&v| v == 0.0));
+    }
+
+    #[test]
+    fn sustain_loop() {
+        let mut adsr = ADSR::new(0.2, 0.3, 0.3, 0.3, 100.0);
+        adsr.set_mode(EnvelopeMode::SustainLoop);
+        adsr.set_param(DecayCurve(0.5));
+        adsr.set_param(LoopStart(0.2));
+        let out = render_chart("chart/sustain_loop.png", "sustain_loop", &mut adsr, 2.0, &[(0.0, NoteOn), (1.6, NoteOff)]);
+        // back to the loop start at the end of the attack every 30 samples while held, the release
+        // from wherever the note off catches it
+        assert_samples(&[20, 50, 80, 110, 140].map(|i| out[i]), &[1.0; 5]);
+        assert!(out[20..50].iter().zip(&out[110..140]).all(|(a, b)| (a - b).abs() < 1e-6));
+        assert!(out[49] > 0.3 && out[159] > 0.3);
+        assert!(out[189] > 0.0 && out[190..].iter().all(|&v| v == 0.0));
+    }
+
+    #[test]
+    fn peak_level() {
+        let mut adsr = ADSR::new(0.2, 0.3, 0.4, 0.5, 100.0);
+        adsr.set_param(PeakLevel(0.7));
+        adsr.set_param(DecayCurve(0.5));
+        let out = render_chart("chart/peak_level.png", "peak_level", &mut adsr, 2.0, &[(0.0, NoteOn), (1.0, NoteOff)]);
+        // the attack stops at the peak level and the decay starts from there
+        assert!((out[20] - 0.7).abs() < 1e-6);
+        assert!(out.iter().all(|&v| v <= out[20]));
+        assert!(out[10] < 0.7 && out[21] < 0.7 && out[21] > 0.4);
+        assert_samples(&out[50..=100], &[0.4; 51]);
+    }
+
+    #[test]
+    fn retargeting() {
+        let steps = |out: &[f32]| out.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max);
+
+        // a shorter release carries on from where the old one was, at the new rate
+        let mut adsr = ADSR::new(0.0, 0.0, 1.0, 1.0, 100.0);
+        adsr.note_on();
+        let _: Vec<f32> = adsr.fill_next(5).collect();
+        adsr.note_off();
+        let mut out: Vec<f32> = adsr.fill_next(51).collect();
+        assert!((out[50] - 0.5).abs() < 1e-4);
+        adsr.set_param(ADSRParamKind::ReleaseTime(0.1));
+        out.extend(adsr.fill_next(7));
+        assert!((out[51] - 0.49).abs() < 1e-4 && (out[52] - 0.39).abs() < 1e-4, "{:?}", &out[48..]);
+        assert_eq!(out[56], 0.0);
+        assert!(steps(&out[1..]) < 0.11);
+
+        // a sustain level moved during the decay, either way
+        for sustain in [0.0, 0.8] {
+            let mut adsr = ADSR::new(0.0, 1.0, 0.2, 0.5, 100.0);
+            adsr.note_on();
+            let mut out: Vec<f32> = adsr.fill_next(51).collect();
+            adsr.set_param(ADSRParamKind::SustainLevel(sustain));
+            out.extend(adsr.fill_next(100));
+            assert!(steps(&out[1..]) < 0.02, "{:?}", &out[45..60]);
+            assert!((out[150] - sustain).abs() < 1e-4);
+        }
+
+        // a hold cut shorter than the time it has run moves on to the decay
+        let mut params = ADSRParams::new(0.0, 0.5, 0.0, 0.1, 0.0, 0.0, 0.0);
+        params.set_param(ADSRParamKind::HoldTime(1.0));
+        let mut adsr = ADSR::with_params(params, 100.0);
+        adsr.note_on();
+        let mut out: Vec<f32> = adsr.fill_next(21).collect();
+        adsr.set_param(ADSRParamKind::HoldTime(0.1));
+        out.extend(adsr.fill_next(3));
+        assert_eq!(out[21], 1.0);
+        assert!((out[22] - 0.98).abs() < 1e-4, "{:?}", &out[18..]);
+    }
+
+    #[test]
+    fn min_ramp() {
+        let mut adsr = ADSR::new(0.0, 0.0, 0.5, 0.0, 100.0);
+        adsr.set_min_ramp(0.04);
+        adsr.schedule_event(0, NoteOn);
+        adsr.schedule_event(6, NoteOff);
+        let out: Vec<f32> = adsr.fill_next(12).collect();
+        let want = [0.0, 0.125, 0.25, 0.375, 0.5, 0.5, 0.5, 0.375, 0.25, 0.125, 0.0, 0.0];
+        assert!(out.iter().zip(want).all(|(x, w)| (x - w).abs() < 1e-6), "{out:?}");
+        adsr.set_min_ramp(0.0);
+        adsr.note_on();
+        assert_eq!(adsr.next(), 0.5); // a jump again
+    }
+
+    #[test]
+    fn always_peak() {
+        let mut adsr = ADSR::new(0.02, 0.0, 0.5, 0.0, 100.0);
+        adsr.note_on();
+        assert_eq!(adsr.fill_next(4).collect::<Vec<f32>>(), vec![0.0, 0.25, 0.5, 0.5]);
+        let mut adsr = ADSR::new(0.02, 0.0, 0.5, 0.0, 100.0);
+        adsr.set_always_peak(true);
+        adsr.note_on();
+        assert_eq!(adsr.fill_next(5).collect::<Vec<f32>>(), vec![0.0, 0.5, 1.0, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn start_level() {
+        let mut adsr = ADSR::new(0.4, 0.3, 0.6, 0.5, 100.0);
+        adsr.set_param(StartLevel(0.3));
+        adsr.set_param(AttackCurve(0.5));
+        let out = render_chart("chart/start_level.png", "start_level", &mut adsr, 2.0, &[(0.0, NoteOn), (1.0, NoteOff)]);
+        // the attack rises from the start level rather than from zero
+        assert_samples(&out[..1], &[0.3]);
+        assert!(out[..40].windows(2).all(|w| w[1] > w[0]));
+        assert!((out[40] - 1.0).abs() < 1e-6);
+        assert!(out[..=100].iter().all(|&v| v >= 0.3));
+        assert!(out[150..].iter().all(|&v| v == 0.0));
+    }
+
+    #[test]
+    fn bipolar_depth() {
+        let mut adsr = ADSR::new(0.0, 0.0, 1.0, 0.0, 100.0);
+        adsr.set_polarity(Polarity::Bipolar);
+        adsr.set_depth(0.5);
+        assert_eq!(adsr.next(), -0.5);
+        adsr.set_next_event(NoteOn);
+        assert_eq!(adsr.next(), 0.5);
+        adsr.set_depth(-1.0);
+        assert_eq!(adsr.next(), -1.0);
+    }
+
+    #[test]
+    fn output_range_mapping() {
+        let params = ADSRParams::new(0.01, 0.01, 0.5, 0.01, 0.0, 0.0, 0.0);
+        let mut adsr = ADSR::with_params(params.clone(), 1000.0);
+        adsr.set_output_range(200.0, 8000.0);
+        assert_eq!(adsr.next(), 200.0);
+        adsr.set_next_event(NoteOn);
+        let out: Vec<f32> = (0..30).map(|_| adsr.next()).collect();
+        assert!(out.iter().all(|x| (200.0..=8000.0).contains(x)));
+        assert!(out.contains(&8000.0));
+        assert_eq!(out[29], 4100.0);
+
+        // the same through the block path, which applies the range as an offset and a gain
+        let mut bank = EnvelopeBank::new(params.clone(), 1, 1000.0);
+        bank.voice_mut(0).set_output_range(200.0, 8000.0);
+        bank.note_on(60, 1.0);
+        let mut block = vec![0.0; 30];
+        bank.mix_block(&mut block);
+        assert!(block.iter().zip(&out).all(|(a, b)| (a - b).abs() < 1e-2));
+
+        let mut adsr = ADSR::with_params(params, 1000.0);
+        adsr.set_polarity(Polarity::Bipolar);
+        adsr.set_output_range(-1.0, 1.0);
+        assert_eq!(adsr.next(), -3.0); // bipolar -1 extends below min
+    }
+
+    #[test]
+    fn depth_around_base() {
+        let mut adsr = ADSR::new(0.0, 0.0, 1.0, 0.0, 100.0);
+        adsr.set_output_range(1000.0, 5000.0);
+        adsr.set_depth(0.5);
+        assert_eq!(adsr.next(), 1000.0);
+        adsr.set_next_event(NoteOn);
+        assert_eq!(adsr.next(), 3000.0);
+        adsr.set_depth(-0.25);
+        assert_eq!(adsr.next(), 0.0);
+        adsr.set_depth(0.0);
+        assert_eq!(adsr.next(), 1000.0);
+    }
+
+    #[test]
+    fn decibel_scale() {
+        let mut adsr = ADSR::new(0.0, 0.0, 0.5, 1.0, 1000.0);
+        adsr.set_param(ReleaseCurve(0.0));
+        adsr.set_level_scale(LevelScale::Decibels(96.0));
+        adsr.set_next_event(NoteOn);
+        adsr.next();
+        assert!((adsr.level() - 0.5).abs() < 1e-5);
+        assert!((adsr.level_db() + 6.0206).abs() < 1e-3);
+        adsr.set_next_event(NoteOff);
+        let mut db = vec![];
+        for _ in 0..1001 {
+            adsr.next();
+            db.push(adsr.level_db());
+        }
+        // the same number of dB every sample, down to silence at -96 dB
+        let step = (96.0 - 6.0206) / 1000.0;
+        assert!(db.windows(2).take(999).all(|w| (w[0] - w[1] - step).abs() < 1e-3));
+        assert!(db[999] < -95.9 && adsr.value() == 0.0 && adsr.level_db() == f32::NEG_INFINITY);
+
+        // switching back carries the level held over
+        adsr.set_next_event(NoteOn);
+        adsr.next();
+        adsr.set_level_scale(LevelScale::Linear);
+        assert!((adsr.value() - 0.5).abs() < 1e-5);
+        assert_eq!(adsr.next(), 0.5);
+    }
+
+    #[test]
+    fn inverted() {
+        let mut adsr = ADSR::new(0.1, 0.2, 0.6, 0.4, 100.0);
+        adsr.set_param(ReleaseCurve(0.5));
+        let mut upright = adsr.clone();
+        adsr.set_inverted(true);
+        let events = [(0.2, NoteOn), (0.6, NoteOff), (0.9, NoteOn), (1.3, NoteOff)];
+        let out = render_chart("chart/inverted.png", "inverted", &mut adsr, 2.0, &events);
+        // one minus the envelope, resting at one while silent and dipping to zero at the peak
+        let expected = upright.render(&events, 2.0);
+        assert!(out.iter().zip(&expected).all(|(v, e)| (v - (1.0 - e)).abs() < 1e-6));
+        assert!(out[..=20].iter().all(|&v| v == 1.0));
+        assert!(out[30].abs() < 1e-6 && (out[50] - 0.4).abs() < 1e-6);
+    }
+
+    #[test]
+    fn shapes() {
+        let mut adsr = ADSR::new(0.4, 0.4, 0.4, 0.8, 100.0);
+        adsr.set_param(AttackShape(CurveKind::SCurve(0.8)));
+        adsr.set_param(DecayShape(CurveKind::Bezier(0.9, 0.1)));
+        adsr.set_param(ReleaseShape(CurveKind::SCurve(-0.8)));
+        let out = render_chart("chart/shapes.png", "shapes", &mut adsr, 2.0, &[(0.0, NoteOn), (1.0, NoteOff)]);
+        // every stage follows its shape from its start level to its end level
+        let follows = |from: usize, len: usize, start: f32, end: f32, kind: CurveKind| {
+            (0..=len).all(|i| {
+                let x = i as f32 / len as f32;
+                // falling stages run the shape backwards from their end level
+                let level = if end >= start {
+                    start + (end - start) * kind.eval(x)
+                } else {
+                    end + (start - end) * kind.eval(1.0 - x)
+                };
+                (out[from + i] - level).abs() < 1e-4
+            })
+        };
+        assert!(follows(0, 40, 0.0, 1.0, CurveKind::SCurve(0.8)));
+        assert!(follows(40, 40, 1.0, 0.4, CurveKind::Bezier(0.9, 0.1)));
+        assert!(follows(100, 80, 0.4, 0.0, CurveKind::SCurve(-0.8)));
+        assert!((out[20] - 0.5).abs() < 1e-4 && (out[140] - 0.2).abs() < 1e-4);
+    }
+
+    #[test]
+    fn custom_shapes() {
+        fn quadratic(x: f32) -> f32 {
+            x * x
+        }
+        let steps = 4.0;
+        let mut adsr = ADSR::new(0.4, 0.4, 0.5, 0.8, 100.0);
+        adsr.set_param(AttackShape(CurveKind::Func(quadratic)));
+        adsr.set_param(DecayShape(CurveKind::custom(|x: f32| x.sqrt())));
+        adsr.set_param(ReleaseShape(CurveKind::custom(move |x: f32| (x * steps).ceil() / steps)));
+        let out = render_chart("chart/custom_shapes.png", "custom_shapes", &mut adsr, 2.0, &[(0.0, NoteOn), (1.0, NoteOff)]);
+        // a quarter of the way up halfway through the attack, the decay a quarter of the way from
+        // sustain with a quarter left to go, and the release down the four steps
+        assert!((out[20] - 0.25).abs() < 1e-6 && (out[70] - 0.75).abs() < 1e-6);
+        let release: Vec<f32> = [110, 130, 150, 170, 180].iter().map(|&i| out[i]).collect();
+        assert!(release.iter().zip([0.5, 0.375, 0.25, 0.125, 0.0]).all(|(v, e)| (v - e).abs() < 1e-6));
+    }
+
+    #[test]
+    fn curve_kinds() {
+        let kinds = [
+            CurveKind::Linear,
+            CurveKind::Exponential { tension: 4.0 },
+            CurveKind::Logarithmic { tension: 4.0 },
+            CurveKind::Cosine,
+        ];
+        for kind in kinds.iter() {
+            assert!(kind.is_valid());
+            assert!(kind.eval(0.0).abs() < 1e-6 && (kind.eval(1.0) - 1.0).abs() < 1e-6);
+            let ys: Vec<f32> = (0..=100).map(|i| kind.eval(i as f32 / 100.0)).collect();
+            assert!(ys.windows(2).all(|w| w[0] <= w[1]));
+        }
+        assert!(CurveKind::Exponential { tension: 4.0 }.eval(0.5) < 0.5);
+        assert!(CurveKind::Logarithmic { tension: 4.0 }.eval(0.5) > 0.5);
+        assert!(!CurveKind::Exponential { tension: -1.0 }.is_valid());
+        let table = CurveKind::Cosine.table(5);
+        assert!(table.eval(0.0) == 0.0 && table.eval(0.5) == 0.5 && table.eval(1.0) == 1.0);
+        assert!((table.eval(0.125) - (table.eval(0.0) + table.eval(0.25)) / 2.0).abs() < 1e-6);
+        assert!(!CurveKind::Table(Arc::from([0.0])).is_valid());
+
+        let mut event_queue = VecDeque::new();
+        event_queue.push_front((0.0, NoteOn));
+        event_queue.push_front((1.0, NoteOff));
+        let mut adsr = ADSR::new(0.4, 0.4, 0.5, 0.8, 100.0);
+        adsr.set_param(AttackShape(CurveKind::Logarithmic { tension: 5.0 }));
+        adsr.set_param(DecayShape(CurveKind::Cosine));
+        adsr.set_param(ReleaseShape(CurveKind::Exponential { tension: 5.0 }));
+        create_chart("chart/curve_kinds.png", "curve_kinds", &mut adsr, 2.0, &mut event_queue);
+    }
+
+    #[test]
+    fn asr() {
+        let mut adsr = ADSR::new(0.3, 0.2, 0.2, 0.5, 100.0);
+        adsr.set_mode(EnvelopeMode::ASR);
+        adsr.set_param(HoldTime(0.2));
+        adsr.set_param(AttackCurve(-0.5));
+        let out = render_chart("chart/asr.png", "asr", &mut adsr, 2.0, &[(0.0, NoteOn), (1.2, NoteOff)]);
+        // at the peak from the end of the attack to the note off, no hold, decay or sustain level
+        assert!(out[29] < 1.0);
+        assert!(out[30..=120].iter().all(|&v| (v - 1.0).abs() < 1e-6));
+        assert!(out[121] < 1.0 && out[169] > 0.0);
+        assert!(out[170..].iter().all(|&v| v == 0.0));
+    }
+
+    #[test]
+    fn fixed_gate() {
+        let mut adsr = ADSR::new(0.0, 0.0, 1.0, 0.0, 100.0);
+        adsr.trigger_for(0.03);
+        let out: Vec<f32> = (0..5).map(|_| adsr.next()).collect();
+        assert_eq!(out, vec![1.0, 1.0, 1.0, 0.0, 0.0]);
+        adsr.trigger_for_samples(2);
+        adsr.next();
+        adsr.trigger_for_samples(2); // retrigger before the first gate ends
+        let out: Vec<f32> = (0..4).map(|_| adsr.next()).collect();
+        assert_eq!(out, vec![1.0, 1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn loop_count() {
+        let mut adsr = ADSR::new(0.1, 0.2, 0.4, 0.3, 100.0);
+        adsr.set_mode(EnvelopeMode::SustainLoop);
+        adsr.set_loop_count(LoopCount::Times(2));
+        adsr.set_param(DecayCurve(0.5));
+        let out = render_chart("chart/loop_count.png", "loop_count", &mut adsr, 2.0, &[(0.0, NoteOn), (1.6, NoteOff)]);
+        // the 30 samples of attack and decay play three times, then the sustain holds to the note off
+        let peaks: Vec<usize> = (0..out.len()).filter(|&i| (out[i] - 1.0).abs() < 1e-6).collect();
+        assert_eq!(peaks, vec![10, 40, 70]);
+        assert!(out[..30].iter().zip(&out[60..90]).all(|(a, b)| (a - b).abs() < 1e-6));
+        assert_samples(&out[90..=160], &[0.4; 71]);
+        assert!(out[190..].iter().all(|&v| v == 0.0));
+    }
+
+    #[test]
+    fn kill() {
+        let mut adsr = ADSR::new(0.0, 0.0, 0.8, 1.0, 100.0);
+        adsr.set_next_event(NoteOn);
+        adsr.next();
+        adsr.kill(4);
+        adsr.set_next_event(NoteOn); // waits for the fade
+        let out: Vec<f32> = (0..5).map(|_| adsr.next()).collect();
+        assert_eq!(out, vec![0.6, 0.4, 0.2, 0.0, 0.8]);
+        adsr.kill(0);
+        assert!(adsr.current_phase == ADSRPhase::Silence);
+        assert_eq!(adsr.next(), 0.0);
+    }
+
+    #[test]
+    fn reset() {
+        let mut adsr = ADSR::new(0.0, 0.1, 0.5, 1.0, 100.0);
+        adsr.set_mode(EnvelopeMode::Percussive);
+        adsr.set_sustain_pedal(true);
+        adsr.note_on();
+        adsr.schedule_event(20, NoteOff);
+        (0..5).for_each(|_| { adsr.next(); });
+        adsr.reset();
+        assert!(adsr.is_finished() && adsr.clock() == 0 && adsr.value() == 0.0);
+
+        let mut fresh = ADSR::new(0.0, 0.1, 0.5, 1.0, 100.0);
+        fresh.set_mode(EnvelopeMode::Percussive);
+        adsr.note_on();
+        fresh.note_on();
+        for _ in 0..30 {
+            assert_eq!(adsr.next(), fresh.next());
+        }
+    }
+
+    #[test]
+    fn save_restore_state() {
+        let mut adsr = ADSR::new(0.1, 0.1, 0.5, 0.2, 100.0);
+        adsr.note_on();
+        adsr.schedule_event(25, NoteOff);
+        (0..15).for_each(|_| { adsr.next(); });
+        let state = adsr.save_state();
+        let first: Vec<f32> = (0..40).map(|_| adsr.next()).collect();
+        adsr.restore_state(state.clone());
+        let again: Vec<f32> = (0..40).map(|_| adsr.next()).collect();
+        assert_eq!(first, again);
+
+        // thaw into another voice with the same parameters
+        let mut other = ADSR::new(0.1, 0.1, 0.5, 0.2, 100.0);
+        other.restore_state(state);
+        assert_eq!(other.clock(), 15);
+        let thawed: Vec<f32> = (0..40).map(|_| other.next()).collect();
+        assert_eq!(first, thawed);
+    }
+
+    #[test]
+    fn clone_running() {
+        let mut adsr = ADSR::new(0.1, 0.1, 0.5, 0.2, 100.0);
+        adsr.set_mode(EnvelopeMode::SustainLoop);
+        adsr.set_depth(0.5);
+        adsr.set_control_period(4);
+        #[cfg(feature = "std")]
+        let _receiver = adsr.phase_channel(64);
+        adsr.note_on();
+        adsr.schedule_event(60, NoteOff);
+        (0..15).for_each(|_| { adsr.next(); });
+        let mut unison = adsr.clone();
+        for _ in 0..100 {
+            assert_eq!(adsr.next(), unison.next());
+        }
+    }
+
+    #[test]
+    fn time_scale() {
+        let mut params = ADSRParams::new(0.1, 0.2, 0.5, 0.25, 0.0, 0.0, 0.0);
+        params.set_param(ADSRParamKind::DelayTime(0.05));
+        let mut slow = ADSR::with_params(params.clone(), 100.0);
+        let mut fast = ADSR::with_params(params, 100.0);
+        fast.set_time_scale(0.5);
+        slow.note_on();
+        fast.note_on();
+        let slow_out: Vec<f32> = (0..40).map(|_| slow.next()).collect();
+        let fast_out: Vec<f32> = (0..20).map(|_| fast.next()).collect();
+        for (i, val) in fast_out.iter().enumerate() {
+            assert!((val - slow_out[2 * i]).abs() < 1e-6);
+        }
+        fast.note_off();
+        assert_eq!(fast.time_until_silence(), Some(13)); // half of 25 samples
+    }
+
+    #[test]
+    fn tempo_sync() {
+        let mut adsr = ADSR::new(0.0, 0.0, 1.0, 0.0, 100.0);
+        adsr.set_stage_beats(ADSRPhase::Attack, Some(1.0));
+        adsr.set_stage_beats(ADSRPhase::Release, Some(0.5));
+        adsr.note_on();
+        let out: Vec<f32> = (0..51).map(|_| adsr.next()).collect();
+        assert!((out[25] - 0.5).abs() < 1e-6); // a beat is half a second at 120 bpm
+        assert_eq!(out[50], 1.0);
+
+        adsr.set_bpm(60.0);
+        adsr.note_off();
+        assert_eq!(adsr.time_until_silence(), Some(50));
+
+        // seconds take over from beats
+        adsr.set_param(ADSRParamKind::ReleaseTime(0.1));
+        adsr.set_bpm(240.0);
+        assert_eq!(adsr.time_until_silence(), Some(10));
+    }
+
+    #[test]
+    fn sample_rate_change() {
+        let mut adsr = ADSR::new(1.0, 0.0, 1.0, 1.0, 100.0);
+        adsr.note_on();
+        adsr.schedule_event(60, NoteOff);
+        (0..40).for_each(|_| { adsr.next(); });
+        assert!((adsr.value() - 0.39).abs() < 1e-6);
+        adsr.set_sample_rate(400.0);
+        assert!((adsr.next() - 0.4).abs() < 1e-6); // the timeline went on at 0.4 s
+        assert!((adsr.next() - 0.4025).abs() < 1e-6);
+        let out: Vec<f32> = (0..80).map(|_| adsr.next()).collect();
+        assert!((out[77] - 0.5975).abs() < 1e-6);
+        assert!(adsr.current_phase() == ADSRPhase::Release); // the note off still comes at 0.6 s
+        assert!((adsr.time_until_silence().unwrap() as i64 - 398).abs() <= 1);
+    }
+
+    #[test]
+    fn sample_rate_change_far_ahead() {
+        let mut adsr = ADSR::new(0.0, 0.0, 1.0, 0.0, 48000.0);
+        adsr.schedule_event(20_000_001, NoteOn); // past what f32 holds to the sample
+        adsr.set_sample_rate(96000.0);
+        assert!(adsr.scheduled.front().is_some_and(|&(at, _)| at == 40_000_002));
+    }
+
+    #[test]
+    fn sample_rate_change_smoothing() {
+        let mut adsr = ADSR::new(0.0, 0.0, 0.2, 1.0, 100.0);
+        adsr.set_param_smoothing(0.1);
+        adsr.note_on();
+        adsr.next();
+        adsr.set_param(ADSRParamKind::SustainLevel(0.6));
+        (0..5).for_each(|_| { adsr.next(); }); // half way there after 50 ms
+        assert!((adsr.value() - 0.4).abs() < 1e-6);
+        adsr.set_sample_rate(400.0);
+        let out: Vec<f32> = (0..20).map(|_| adsr.next()).collect();
+        assert!((out[9] - 0.5).abs() < 1e-6); // still 100 ms in all, now 40 samples
+        assert!((out[19] - 0.6).abs() < 1e-6);
+        adsr.set_param(ADSRParamKind::SustainLevel(0.2));
+        let out: Vec<f32> = (0..40).map(|_| adsr.next()).collect();
+        assert!((out[19] - 0.4).abs() < 1e-6 && out[39] == 0.2);
+    }
+
+    #[test]
+    fn humanize() {
+        let notes = |seed: u64| {
+            let mut adsr = ADSR::new(0.1, 0.1, 0.5, 0.1, 100.0);
+            adsr.set_humanize(0.2, 0.1, seed);
+            let mut peaks = Vec::new();
+            for _ in 0..4 {
+                adsr.set_next_event(Retrigger);
+                let out: Vec<f32> = (0..40).map(|_| adsr.next()).collect();
+                let sustain = out[39];
+                assert!((0.45..=0.55).contains(&sustain));
+                // the attack ends within 20% of 10 samples, at the peak
+                let top = out.iter().position(|&v| v == out.iter().cloned().fold(0.0, f32::max)).unwrap();
+                assert!((8..=12).contains(&top));
+                peaks.push((top, sustain));
+            }
+            peaks
+        };
+        let a = notes(1);
+        assert!(a == notes(1)); // repeatable
+        assert!(a != notes(2));
+        assert!(a.iter().any(|&n| n != a[0])); // notes differ from each other
+    }
+
+    #[test]
+    fn param_crossfade() {
+        let mut adsr = ADSR::new(0.0, 0.0, 0.5, 0.0, 100.0);
+        adsr.set_param_crossfade(0.04);
+        adsr.note_on();
+        adsr.next();
+        adsr.set_param(SustainLevel(0.9));
+        let out: Vec<f32> = (0..5).map(|_| adsr.next()).collect();
+        for (val, expected) in out.iter().zip([0.6, 0.7, 0.8, 0.9, 0.9]) {
+            assert!((val - expected).abs() < 1e-6);
         }
+
+        // a new note starts with the new parameters right away
+        adsr.set_param(SustainLevel(0.1));
+        adsr.set_next_event(Retrigger);
+        adsr.next();
+        assert!((adsr.next() - 0.1).abs() < 1e-6);
+
+        adsr.set_param_crossfade(0.0);
+        adsr.set_param(SustainLevel(0.3));
+        assert!((adsr.next() - 0.3).abs() < 1e-6);
     }
-}
 
-impl Signal for ADSR {
-    type Frame = f32;
+    #[test]
+    fn param_smoothing() {
+        let mut adsr = ADSR::new(0.0, 0.0, 0.5, 0.0, 100.0);
+        adsr.set_param_smoothing(0.04);
+        adsr.note_on();
+        adsr.next();
+        adsr.set_param(SustainLevel(0.9));
+        assert!((adsr.peek() - 0.6).abs() < 1e-6);
+        let out: Vec<f32> = (0..5).map(|_| adsr.next()).collect();
+        for (val, expected) in out.iter().zip([0.6, 0.7, 0.8, 0.9, 0.9]) {
+            assert!((val - expected).abs() < 1e-6);
+        }
 
-    fn next(&mut self) -> Self::Frame {
-        self.generate()
+        // a new target takes over from where the glide is
+        adsr.set_param(SustainLevel(0.1));
+        assert!((adsr.next() - 0.7).abs() < 1e-6);
+        adsr.set_param(SustainLevel(0.9));
+        let out: Vec<f32> = (0..4).map(|_| adsr.next()).collect();
+        for (val, expected) in out.iter().zip([0.75, 0.8, 0.85, 0.9]) {
+            assert!((val - expected).abs() < 1e-6);
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use plotters::prelude::*;
-    use ADSREvent::*;
-    use ADSRParamKind::*;
-    use std::collections::VecDeque;
+    #[test]
+    fn panic() {
+        let mut adsr = ADSR::new(0.0, 0.0, 0.6, 1.0, 2000.0);
+        adsr.set_sustain_pedal(true);
+        adsr.note_on();
+        adsr.schedule_event(5, NoteOn);
+        adsr.next();
+        adsr.panic(true);
+        let out: Vec<f32> = (0..3).map(|_| adsr.next()).collect();
+        assert!((out[0] - 0.4).abs() < 1e-6 && (out[1] - 0.2).abs() < 1e-6 && out[2] == 0.0);
+        assert!((0..10).all(|_| adsr.next() == 0.0)); // the scheduled note on is gone
+        adsr.note_on();
+        adsr.next();
+        adsr.panic(false);
+        assert_eq!(adsr.next(), 0.0);
+        assert_eq!(adsr.sustain_pedal, 0.0);
+    }
 
-    fn create_chart(filename: &str, cap: &str, adsr: &mut ADSR, t_sec: f32, events: &mut VecDeque<(f32, ADSREvent)>) {
-        let data_len: usize = (adsr.sample_rate * t_sec) as usize;
-        let adsr_vec: Vec<f32> = (0..=data_len).map(|i| {
-            if !events.is_empty() && events[events.len() - 1].0 <= i as f32 / adsr.sample_rate {
-                let e = events.pop_back().unwrap();
-                adsr.set_next_event(e.1);
-            }
-            adsr.next()
-        }).collect();
+    #[test]
+    fn pause() {
+        let mut adsr = ADSR::new(0.04, 0.0, 1.0, 0.0, 100.0);
+        adsr.set_next_event(NoteOn);
+        assert_eq!(adsr.next(), 0.0);
+        assert_eq!(adsr.next(), 0.25);
+        adsr.pause();
+        adsr.set_next_event(NoteOff); // not consumed while paused
+        assert_eq!(adsr.next(), 0.25);
+        assert_eq!(adsr.next(), 0.25);
+        adsr.set_next_event(NoteOn);
+        adsr.resume();
+        assert_eq!(adsr.next(), 0.5);
+    }
 
-        let root = BitMapBackend::new(filename, (1024, 768)).into_drawing_area();
+    #[test]
+    fn retrigger_event() {
+        let mut adsr = ADSR::new(0.02, 0.0, 0.5, 0.0, 100.0);
+        adsr.set_next_event(Retrigger); // from silence like a note on
+        let out: Vec<f32> = (0..4).map(|_| adsr.next()).collect();
+        assert_eq!(out, vec![0.0, 0.25, 0.5, 0.5]);
+        adsr.set_next_event(Retrigger); // no note off in between
+        let out: Vec<f32> = (0..4).map(|_| adsr.next()).collect();
+        assert_eq!(out, vec![0.0, 0.25, 0.5, 0.5]);
+        adsr.set_next_event(NoteOn); // an open gate is not restarted by a plain note on
+        assert_eq!(adsr.next(), 0.5);
+    }
 
-        root.fill(&WHITE).unwrap();
+    #[test]
+    fn trigger() {
+        let mut adsr = ADSR::new(0.02, 0.02, 0.5, 1.0, 100.0);
+        adsr.trigger();
+        adsr.note_off(); // the shot ignores the gate
+        let out: Vec<f32> = (0..6).map(|_| adsr.next()).collect();
+        assert_samples(&out, &[0.0, 0.5, 1.0, 0.5, 0.0, 0.0]);
+        adsr.note_on(); // back to the gated mode afterwards
+        let out: Vec<f32> = (0..6).map(|_| adsr.next()).collect();
+        assert_eq!(out, vec![0.0, 0.5, 1.0, 0.75, 0.5, 0.5]);
+    }
 
-        let mut chart = ChartBuilder::on(&root)
-            .set_label_area_size(LabelAreaPosition::Left, 60)
-            .set_label_area_size(LabelAreaPosition::Bottom, 60)
-            .caption(cap, ("sans-serif", 40))
-            .build_cartesian_2d(-0.5_f32..t_sec+1.0, -0.1_f32..1.1_f32)
-            .unwrap();
+    #[test]
+    #[cfg(feature = "std")]
+    fn phase_callback() {
+        use std::sync::{Arc, Mutex};
+        use ADSRPhase::*;
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&changes);
+        let mut adsr = ADSR::new(0.02, 0.02, 0.5, 0.02, 100.0);
+        adsr.on_phase_change(move |from, to| log.lock().unwrap().push((from, to)));
+        adsr.note_on();
+        (0..6).for_each(|_| { adsr.next(); });
+        adsr.note_off();
+        (0..4).for_each(|_| { adsr.next(); });
+        adsr.note_on();
+        adsr.next();
+        adsr.kill(2);
+        (0..3).for_each(|_| { adsr.next(); });
+        assert!(*changes.lock().unwrap() == vec![
+            (Silence, Attack), (Attack, Decay), (Decay, Sustain), (Sustain, Release), (Release, Silence),
+            (Silence, Attack), (Attack, Shutdown), (Shutdown, Silence),
+        ]);
+    }
 
-        chart
-            .configure_mesh()
-            //.disable_x_mesh()
-            //.disable_y_mesh()
-            .draw()
-            .unwrap();
+    #[test]
+    #[cfg(feature = "std")]
+    fn phase_channel() {
+        let mut adsr = ADSR::new(0.02, 0.0, 0.5, 0.0, 100.0);
+        let receiver = adsr.phase_channel(2);
+        adsr.schedule_event(1, NoteOn);
+        adsr.schedule_event(5, NoteOff);
+        (0..8).for_each(|_| { adsr.next(); });
+        let changes: Vec<(u64, ADSRPhase)> = receiver.try_iter().collect();
+        assert!(changes == vec![(1, ADSRPhase::Attack), (3, ADSRPhase::Sustain)]); // the silence did not fit
+    }
 
-        chart.draw_series(
-            AreaSeries::new(
-                (0..=data_len).zip(adsr_vec.iter()).map(|(x, y)| ((x as f32 / adsr.sample_rate, *y))),
-                0.0,
-                &RED.mix(0.2),
-            )
-            .border_style(&RED),
-        ).unwrap();
+    #[test]
+    fn active_finished() {
+        let mut adsr = ADSR::new(0.0, 0.02, 0.0, 0.02, 100.0);
+        adsr.set_mode(EnvelopeMode::Percussive);
+        assert!(!adsr.is_active() && adsr.is_finished());
+        adsr.note_on();
+        assert!(!adsr.is_finished());
+        adsr.next();
+        assert!(adsr.is_active());
+        (0..3).for_each(|_| { adsr.next(); });
+        assert!(!adsr.is_active() && !adsr.is_finished()); // decayed to zero but still held
+        adsr.note_off();
+        (0..3).for_each(|_| { adsr.next(); });
+        assert!(adsr.is_finished());
+        adsr.schedule_event(10, NoteOn);
+        assert!(!adsr.is_finished());
+    }
 
-        // To avoid the IO failure being ignored silently, we manually call the present function
-        root.present().unwrap();
+    #[test]
+    fn getters() {
+        let mut adsr = ADSR::new(0.02, 0.0, 0.5, 0.0, 100.0);
+        adsr.set_inverted(true);
+        assert!(adsr.current_phase() == ADSRPhase::Silence && adsr.value() == 1.0);
+        adsr.note_on();
+        adsr.next();
+        let out = adsr.next();
+        assert!(adsr.current_phase() == ADSRPhase::Attack && adsr.value() == out && out == 0.75);
     }
 
     #[test]
-    fn silence() {
-        let mut event_queue: VecDeque<(f32, ADSREvent)> = VecDeque::new();
-        let mut adsr = ADSR::new(0.1, 0.1, 0.1, 0.1, 100.0);
-        create_chart("chart/silence.png", "silence", &mut adsr, 2.0, &mut event_queue)
+    fn progress() {
+        let mut adsr = ADSR::new(0.04, 0.0, 0.5, 0.02, 100.0);
+        adsr.note_on();
+        adsr.next();
+        adsr.next();
+        assert!(adsr.current_phase() == ADSRPhase::Attack && adsr.phase_progress() == 0.5);
+        assert_eq!(adsr.elapsed(), 0.02);
+        (0..5).for_each(|_| { adsr.next(); });
+        assert!(adsr.current_phase() == ADSRPhase::Sustain && adsr.phase_progress() == 0.0);
+        assert_eq!(adsr.elapsed(), 0.03);
+        adsr.note_off();
+        adsr.next();
+        assert!(adsr.current_phase() == ADSRPhase::Release && adsr.phase_progress() == 0.5);
     }
 
     #[test]
-    fn silence_although_note_on() {
-        let mut event_queue = VecDeque::new();
-        event_queue.push_front((0.1, NoteOn));
-        let mut adsr = ADSR::new(0.0, 0.0, 0.0, 0.0, 100.0);
-        create_chart("chart/silence_although_note_on.png", "silence_although_note_on", &mut adsr, 2.0, &mut event_queue);
+    fn until_silence() {
+        let mut adsr = ADSR::new(0.0, 0.0, 0.5, 0.1, 100.0);
+        assert_eq!(adsr.time_until_silence(), Some(0));
+        adsr.note_on();
+        adsr.next();
+        assert_eq!(adsr.time_until_silence(), Some(10));
+        adsr.note_off();
+        (0..4).for_each(|_| { adsr.next(); });
+        assert_eq!(adsr.time_until_silence(), Some(6));
+        assert_eq!((0..20).take_while(|_| adsr.next() > 0.0).count(), 6);
+        adsr.set_sustain_pedal(true);
+        adsr.note_on();
+        adsr.next();
+        assert_eq!(adsr.time_until_silence(), None);
+        adsr.set_sustain_pedal(false);
+        adsr.kill(3);
+        assert_eq!(adsr.time_until_silence(), Some(3));
     }
 
     #[test]
-    fn just_sustain() {
-        let mut event_queue = VecDeque::new();
-        event_queue.push_front((0.0, NoteOn));
-        let mut adsr = ADSR::new(0.0, 0.0, 1.0, 0.0, 100.0);
-        create_chart("chart/just_sustain.png", "just_sustain", &mut adsr, 2.0, &mut event_queue);
+    fn note_on_off() {
+        let mut adsr = ADSR::new(0.0, 0.0, 0.5, 0.0, 100.0);
+        adsr.note_on();
+        assert_eq!(adsr.next(), 0.5);
+        adsr.note_off();
+        assert_eq!(adsr.next(), 0.0);
+        adsr.gate(true);
+        assert_eq!(adsr.next(), 0.5);
+        adsr.gate(false);
+        assert_eq!(adsr.next(), 0.0);
     }
 
     #[test]
-    fn typical() {
-        let mut event_queue = VecDeque::new();
-        event_queue.push_front((0.0, NoteOn));
-        event_queue.push_front((1.0, NoteOff));
-        let mut adsr = ADSR::new(0.2, 0.2, 0.8, 1.0, 100.0);
-        adsr.set_param(AttackCurve(-0.5));
-        adsr.set_param(DecayCurve(0.4));
-        adsr.set_param(ReleaseCurve(0.6));
-        create_chart("chart/typical.png", "typical", &mut adsr, 2.0, &mut event_queue);
+    fn velocity() {
+        let mut adsr = ADSR::new(0.1, 0.2, 0.6, 0.3, 100.0);
+        adsr.set_param(VelocityToPeak(1.0));
+        adsr.set_param(VelocityToSustain(0.5));
+        let events = [(0.0, NoteOnWithVelocity(0.3)), (0.5, NoteOff), (1.0, NoteOnWithVelocity(0.8)), (1.5, NoteOff)];
+        let out = render_chart("chart/velocity.png", "velocity", &mut adsr, 2.0, &events);
+        // the peak follows the velocity fully, the sustain by half: 0.6 * (1 - 0.5 * (1 - velocity))
+        let near = |i: usize, level: f32| (out[i] - level).abs() < 1e-6;
+        assert!(near(10, 0.3) && near(30, 0.39) && near(50, 0.39));
+        assert!(near(110, 0.8) && near(130, 0.54) && near(150, 0.54));
+        assert!(out[80..=100].iter().all(|&v| v == 0.0));
     }
 
     #[test]
-    fn fade_in_out() {
+    fn velocity_curves() {
+        let table: Arc<[f32]> = (0..128).map(|v| if v < 64 { 0.0 } else { 1.0 }).collect();
+        let peak = |curve: VelocityCurve, velocity: f32| {
+            let mut adsr = ADSR::new(0.01, 0.1, 0.0, 0.1, 1000.0);
+            adsr.set_param(VelocityToPeak(1.0));
+            adsr.set_param(VelocityResponse(curve));
+            adsr.set_next_event(NoteOnWithVelocity(velocity));
+            (0..20).map(|_| adsr.next()).fold(0.0, f32::max)
+        };
+        assert!((peak(VelocityCurve::Linear, 0.5) - 0.5).abs() < 1e-6);
+        assert!((peak(VelocityCurve::Soft, 0.5) - 0.75).abs() < 1e-6);
+        assert!((peak(VelocityCurve::Hard, 0.5) - 0.25).abs() < 1e-6);
+        assert_eq!(peak(VelocityCurve::Table(table.clone()), 63.0 / 127.0), 0.0);
+        assert_eq!(peak(VelocityCurve::Table(table), 64.0 / 127.0), 1.0);
+        assert_eq!(peak(VelocityCurve::Hard, 1.0), 1.0);
+
+        let mut params = ADSRParams::new(0.01, 0.1, 0.2, 0.1, 0.0, 0.0, 0.0);
+        let invalid = VelocityResponse(VelocityCurve::Table(Arc::from([0.0, 1.5])));
+        assert!(params.try_set_param(invalid) == Err(ADSRError::InvalidShape { param: "velocity response" }));
+    }
+
+    #[test]
+    fn key_tracking() {
+        let mut adsr = ADSR::new(0.0, 0.0, 1.0, 0.1, 100.0);
+        adsr.set_param(KeyTracking(1.0));
+        let release_samples = |adsr: &mut ADSR, key: u8| {
+            adsr.set_key(key);
+            adsr.note_on();
+            adsr.next();
+            adsr.note_off();
+            (0..100).take_while(|_| adsr.next() > 0.0).count()
+        };
+        assert_eq!(release_samples(&mut adsr, 60), 10);
+        assert_eq!(release_samples(&mut adsr, 72), 5);
+        assert_eq!(release_samples(&mut adsr, 48), 20);
+    }
+
+    #[test]
+    fn sustain_pedal() {
         let mut event_queue = VecDeque::new();
         event_queue.push_front((0.0, NoteOn));
-        event_queue.push_front((1.5, NoteOff));
-        let mut adsr = ADSR::new(0.5, 0.0, 0.8, 0.5, 100.0);
-        create_chart("chart/fade_in_out.png", "fade_in_out", &mut adsr, 2.0, &mut event_queue);
+        event_queue.push_front((0.3, NoteOff)); // held by the pedal
+        event_queue.push_front((0.6, NoteOn));  // struck again under the pedal
+        event_queue.push_front((0.8, NoteOff));
+        let mut adsr = ADSR::new(0.1, 0.3, 0.5, 0.3, 100.0);
+        adsr.set_sustain_pedal(true);
+        let data_len = 200;
+        let out: Vec<f32> = (0..data_len).map(|i| {
+            if !event_queue.is_empty() && event_queue[event_queue.len() - 1].0 <= i as f32 / 100.0 {
+                adsr.set_next_event(event_queue.pop_back().unwrap().1);
+            }
+            if i == 120 {
+                adsr.set_sustain_pedal(false);
+            }
+            adsr.next()
+        }).collect();
+        assert_eq!(out[50], 0.5);
+        assert_eq!(out[60], 0.0);
+        assert_eq!(out[119], 0.5);
+        assert!(out[121] < 0.5);
+        assert_eq!(out[150], 0.0);
     }
 
     #[test]
-    fn curvature_edge_case() {
+    fn gate_calls_in_order() {
+        let mut adsr = ADSR::new(0.0, 0.0, 0.5, 0.02, 100.0);
+        adsr.set_sustain_pedal(true);
+        adsr.note_on();
+        adsr.next();
+        adsr.note_off(); // held by the pedal
+        adsr.next();
+        adsr.note_on(); // struck again, then the pedal comes up with the key still down
+        adsr.set_sustain_pedal(false);
+        assert_eq!(adsr.next(), 0.5);
+        assert_eq!(adsr.next(), 0.5);
+
+        adsr.note_off();
+        adsr.note_on(); // a note on before kill() is closed by it, one after waits for the fade
+        adsr.kill(2);
+        let out: Vec<f32> = (0..4).map(|_| adsr.next()).collect();
+        assert_eq!(out, vec![0.25, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn half_pedal() {
+        let mut adsr = ADSR::new(0.0, 0.0, 1.0, 0.04, 100.0);
+        adsr.set_sustain_pedal_level(0.5);
+        adsr.note_on();
+        adsr.next();
+        adsr.note_off(); // released at half speed
+        let out: Vec<f32> = (0..5).map(|_| adsr.next()).collect();
+        assert_eq!(out, vec![1.0, 0.875, 0.75, 0.625, 0.5]);
+        adsr.set_sustain_pedal_level(1.0); // pressed down during the release, the tail rings on
+        assert_eq!(adsr.next(), 0.375);
+        assert_eq!(adsr.next(), 0.375);
+        adsr.set_sustain_pedal_level(0.0);
+        let out: Vec<f32> = (0..4).map(|_| adsr.next()).collect();
+        assert_samples(&out, &[0.375, 0.125, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn retrigger_modes() {
         let mut event_queue = VecDeque::new();
         event_queue.push_front((0.0, NoteOn));
+        event_queue.push_front((0.8, NoteOff));
+        event_queue.push_front((1.0, NoteOn)); // still releasing, glides back to sustain
         event_queue.push_front((1.5, NoteOff));
-        let mut adsr = ADSR::new(0.5, 0.5, 0.5, 0.5, 100.0);
-        adsr.set_param(AttackCurve(-1.0));
-        adsr.set_param(DecayCurve(0.0));
-        adsr.set_param(ReleaseCurve(1.0));
-        create_chart("chart/curvature_edge_case.png", "curvature_edge_case", &mut adsr, 2.0, &mut event_queue);
+        let mut adsr = ADSR::new(0.2, 0.3, 0.6, 0.4, 100.0);
+        adsr.set_retrigger_mode(RetriggerMode::Legato);
+        adsr.set_param(DecayCurve(0.5));
+        create_chart("chart/legato.png", "legato", &mut adsr, 2.0, &mut event_queue);
+
+        let retrigger_in_release = |mode: RetriggerMode| {
+            let mut adsr = ADSR::new(0.04, 0.0, 1.0, 0.04, 100.0);
+            adsr.set_retrigger_mode(mode);
+            adsr.note_on();
+            let out: Vec<f32> = (0..5).map(|_| adsr.next()).collect();
+            assert_eq!(out, vec![0.0, 0.25, 0.5, 0.75, 1.0]);
+            adsr.note_off();
+            assert_eq!(adsr.next(), 1.0);
+            assert_eq!(adsr.next(), 0.75);
+            adsr.note_on();
+            (0..5).map(|_| adsr.next()).collect::<Vec<f32>>()
+        };
+        assert_eq!(retrigger_in_release(RetriggerMode::HardReset), vec![0.0, 0.25, 0.5, 0.75, 1.0]);
+        assert_eq!(retrigger_in_release(RetriggerMode::FromCurrentValue), vec![0.75, 1.0, 1.0, 1.0, 1.0]);
+        assert_eq!(retrigger_in_release(RetriggerMode::Legato), vec![0.8125, 0.875, 0.9375, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn re_attack() {
+        let mut adsr = ADSR::new(0.3, 0.2, 0.5, 0.5, 100.0);
+        adsr.set_retrigger_mode(RetriggerMode::FromCurrentValue);
+        adsr.set_param(AttackCurve(-0.5));
+        adsr.set_param(ReleaseCurve(0.5));
+        let events = [(0.0, NoteOn), (0.6, NoteOff), (0.8, NoteOn), (1.4, NoteOff)];
+        let out = render_chart("chart/re_attack.png", "re_attack", &mut adsr, 2.0, &events);
+        // the second attack starts from the level the release got to instead of from zero, and is
+        // shorter by the part of the rise already done
+        assert!(out[79] > 0.0 && out[80] == out[79]);
+        assert!(out[80..=104].windows(2).all(|w| w[1] > w[0]) && out[104] > 0.99);
+        assert!(out[105] < out[104]);
+        assert_samples(&out[130..=140], &[0.5; 11]);
+    }
+
+    #[test]
+    fn block_events() {
+        let mut adsr = ADSR::new(0.02, 0.02, 0.5, 0.02, 100.0);
+        let mut reference = ADSR::new(0.02, 0.02, 0.5, 0.02, 100.0);
+        let events = [(1, NoteOn), (6, NoteOff), (9, NoteOnWithVelocity(0.5))];
+        let mut out = [0.0; 12];
+        adsr.process_block(&mut out, &events);
+        let expected: Vec<f32> = (0..12).map(|i| {
+            for &(_, event) in events.iter().filter(|e| e.0 == i) {
+                reference.set_next_event(event);
+            }
+            reference.next()
+        }).collect();
+        assert_eq!(out.to_vec(), expected);
+        assert_eq!(out[0], 0.0);
+        assert_eq!(out[2], 0.5);
+
+        let mut adsr = ADSR::new(0.02, 0.02, 0.5, 0.02, 100.0);
+        adsr.note_on();
+        let mut block = [0.0; 5];
+        adsr.fill_block(&mut block);
+        assert_eq!(block, [0.0, 0.5, 1.0, 0.75, 0.5]);
+    }
+
+    #[test]
+    fn param_errors() {
+        let error = ADSRParams::try_new(0.1, -0.2, 1.5, 0.1, 0.0, 0.0, 0.0).err();
+        assert!(error == Some(ADSRError::OutOfRange { param: "decay time", value: -0.2, min: 0.0, max: f32::INFINITY }));
+        assert_eq!(error.unwrap().to_string(), "decay time must be at least 0, got -0.2");
+        assert!(ADSR::try_new(0.1, 0.1, 0.5, f32::NAN, 100.0).is_err());
+
+        let mut adsr = ADSR::new(0.1, 0.1, 0.5, 0.1, 100.0);
+        let error = adsr.try_set_param(SustainLevel(1.5)).unwrap_err();
+        assert_eq!(error.to_string(), "sustain level must be in 0..=1, got 1.5");
+        let error = adsr.try_set_param(ReleaseShape(CurveKind::Bezier(2.0, 0.5))).unwrap_err();
+        assert!(error == ADSRError::InvalidShape { param: "release shape" });
+        assert!(adsr.try_set_param(SustainLevel(0.8)).is_ok());
+        adsr.note_on();
+        assert_eq!(adsr.fill_next(40).last(), Some(0.8)); // the rejected values changed nothing
+    }
+
+    #[test]
+    fn non_finite() {
+        let mut adsr = ADSR::new(0.05, 0.05, 0.5, 0.05, 100.0);
+        let error = adsr.try_set_param(HoldTime(f32::INFINITY)).unwrap_err();
+        assert!(error == ADSRError::NotFinite { param: "hold time", value: f32::INFINITY });
+        assert!(adsr.try_set_param(PeakLevel(f32::NAN)).is_err());
+        adsr.set_param_policy(ParamPolicy::Clamp);
+        adsr.set_param(ReleaseTime(f32::INFINITY)); // ignored, no time to clamp to
+        adsr.set_param(AttackCurve(f32::NEG_INFINITY));
+        assert_eq!(adsr.params.release_time, 0.05);
+        assert!(adsr.params.attack_curve == CurveKind::Factor(-1.0));
+
+        // a custom shape gone wrong silences the envelope instead of poisoning later samples
+        adsr.set_param(DecayShape(CurveKind::Func(|x| if x > 0.5 { f32::NAN } else { x })));
+        adsr.note_on();
+        let out: Vec<f32> = (0..10).map(|_| adsr.next()).collect();
+        assert!(out.iter().all(|x| x.is_finite()));
+        assert!(adsr.current_phase() == ADSRPhase::Silence && adsr.is_healthy());
+        assert_eq!(out[9], 0.0);
+        adsr.set_param(DecayShape(CurveKind::Linear));
+        adsr.note_on();
+        assert_eq!(adsr.fill_next(20).last(), Some(0.5)); // and it plays again
+
+        let mut state = adsr.save_state();
+        state.current_val = f32::NAN;
+        adsr.restore_state(state);
+        assert!(!adsr.is_healthy());
+    }
+
+    #[test]
+    fn continuity() {
+        let mut shapes: Vec<CurveKind> = (-10..=10).map(|i| CurveKind::Factor(i as f32 / 10.0)).collect();
+        shapes.extend([
+            CurveKind::SCurve(0.7), CurveKind::Bezier(0.9, 0.1), CurveKind::Exponential { tension: 7.0 },
+            CurveKind::Logarithmic { tension: 7.0 }, CurveKind::Cosine, CurveKind::Func(|x| 0.9 * x), // off at the end
+            CurveKind::Factor(0.3).table(7),
+        ]);
+        for shape in shapes {
+            for hold in [0.0, 0.1] {
+                let mut adsr = ADSR::new(0.3, 0.2, 0.37, 0.4, 1000.0);
+                adsr.set_param(HoldTime(hold));
+                adsr.set_param(AttackShape(shape.clone()));
+                adsr.set_param(DecayShape(shape.clone()));
+                adsr.set_param(ReleaseShape(shape.clone()));
+                adsr.set_param(VelocityToPeak(0.5));
+                adsr.set_next_event(NoteOnWithVelocity(0.3));
+                adsr.next();
+                assert!(adsr.boundary_jumps().iter().all(|&(_, jump)| jump == 0.0));
+                adsr.set_fast_math(true);
+                assert!(adsr.boundary_jumps().iter().all(|&(_, jump)| jump == 0.0));
+            }
+        }
+    }
+
+    #[test]
+    fn output_range() {
+        let shapes = [
+            CurveKind::Factor(1.0), CurveKind::Factor(-1.0), CurveKind::Factor(0.999), CurveKind::SCurve(-1.0),
+            CurveKind::Bezier(0.0, 1.0), CurveKind::Exponential { tension: 80.0 }, CurveKind::Logarithmic { tension: 80.0 },
+            CurveKind::Cosine, CurveKind::Func(|x| 1.001 * x - 0.0005), // a custom shape slightly off
+        ];
+        for (i, shape) in shapes.iter().enumerate() {
+            let mut adsr = ADSR::new(0.013, 0.017, 0.3, 0.019, 44100.0);
+            adsr.set_param(AttackShape(shape.clone()));
+            adsr.set_param(DecayShape(shape.clone()));
+            adsr.set_param(ReleaseShape(shape.clone()));
+            adsr.set_one_pole(i % 2 == 0);
+            adsr.set_fast_math(i % 3 == 0);
+            adsr.schedule_event(0, NoteOn);
+            adsr.schedule_event(1500, NoteOff);
+            let mut out = vec![0.0; 3000];
+            adsr.fill_block(&mut out);
+            assert!(out.iter().all(|x| (0.0..=1.0).contains(x)));
+            assert!(out.iter().any(|&x| x > 0.95)); // and still reaches the peak
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn clamp_policy() {
+        let clamped = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = Arc::clone(&clamped);
+        let mut adsr = ADSR::new(0.0, 0.0, 0.5, 0.0, 100.0);
+        adsr.set_param_policy(ParamPolicy::Clamp);
+        adsr.on_param_clamped(move |error| log.lock().unwrap().push(error));
+        adsr.set_param(SustainLevel(1.0001));
+        adsr.set_param(ReleaseTime(f32::NAN)); // ignored
+        adsr.set_param(ReleaseTime(-0.1));
+        adsr.set_param(SustainLevel(0.9)); // in range, no notification
+        assert_eq!(clamped.lock().unwrap().len(), 3);
+        assert!(clamped.lock().unwrap()[0] == ADSRError::OutOfRange { param: "sustain level", value: 1.0001, min: 0.0, max: 1.0 });
+        assert_eq!(adsr.params.sustain_level, 0.9);
+        assert_eq!(adsr.params.release_time, 0.0);
+        assert!(adsr.try_set_param(SustainLevel(1.0001)).is_err()); // still strict
+    }
+
+    #[test]
+    fn block_rendering() {
+        let voices = |blocks: bool| {
+            let mut voices: Vec<ADSR> = (0..8).map(|i| {
+                let mut adsr = ADSR::new(0.05, 0.1, 0.4, 0.2, 1000.0);
+                adsr.set_param(AttackCurve(-0.6));
+                adsr.set_param(DecayCurve(0.5));
+                adsr.set_param(ReleaseCurve(0.3));
+                adsr.schedule_event(5, NoteOnWithVelocity(0.7));
+                adsr.schedule_event(300 + 10 * i, NoteOff);
+                adsr.schedule_event(500, NoteOn);
+                adsr.schedule_event(700, NoteOff);
+                adsr
+            }).collect();
+            voices[1].set_mode(EnvelopeMode::Percussive);
+            voices[2].set_mode(EnvelopeMode::OneShot);
+            voices[3].set_param(DecayShape(CurveKind::SCurve(0.4))); // generate() all along
+            voices[4].set_one_pole(true);
+            voices[5].set_control_period(4);
+            voices[6].set_sustain_pedal_level(0.5);
+            voices[7].set_polarity(Polarity::Bipolar);
+            voices[7].set_inverted(true);
+            voices.iter_mut().map(|adsr| {
+                let mut out = vec![0.0; 1000];
+                if blocks {
+                    for block in out.chunks_mut(37) {
+                        adsr.fill_block(block);
+                    }
+                } else {
+                    out.iter_mut().for_each(|x| *x = adsr.generate());
+                }
+                out.push(adsr.phase_progress());
+                adsr.note_on(); // and carries on from the same state
+                out.extend(adsr.fill_next(100));
+                out
+            }).collect::<Vec<_>>()
+        };
+        assert_eq!(voices(true), voices(false));
+    }
+
+    #[test]
+    fn block_size_invariance() {
+        let timeline = [
+            (3, NoteOnWithVelocity(0.8)), (140, NoteOff), (141, NoteOn), (300, Retrigger),
+            (300, NoteOff), (301, NoteOn), (655, NoteOff), (900, NoteOnWithVelocity(0.2)), (1200, NoteOff),
+        ];
+        let voice = |variant: usize| {
+            let mut adsr = ADSR::new(0.05, 0.1, 0.4, 0.2, 1000.0);
+            adsr.set_param(AttackCurve(-0.6));
+            adsr.set_param(ReleaseCurve(0.3));
+            match variant {
+                1 => adsr.set_control_period(5),
+                2 => adsr.set_one_pole(true),
+                3 => adsr.set_mode(EnvelopeMode::SustainLoop),
+                _ => {},
+            }
+            adsr
+        };
+        for variant in 0..4 {
+            // one sample at a time, each event set right before its sample
+            let mut adsr = voice(variant);
+            let reference: Vec<f32> = (0..1500u64).map(|n| {
+                for &(_, event) in timeline.iter().filter(|&&(at, _)| at == n) {
+                    adsr.set_next_event(event);
+                }
+                adsr.generate()
+            }).collect();
+
+            // blocks of any size with the events handed over per block
+            for size in [1, 16, 37, 256, 1500] {
+                let mut adsr = voice(variant);
+                let mut out = vec![0.0; 1500];
+                for (i, block) in out.chunks_mut(size).enumerate() {
+                    let start = (i * size) as u64;
+                    let events: Vec<(usize, ADSREvent)> = timeline.iter()
+                        .filter(|&&(at, _)| at >= start && at < start + block.len() as u64)
+                        .map(|&(at, event)| ((at - start) as usize, event))
+                        .collect();
+                    adsr.process_block(block, &events);
+                }
+                assert_eq!(out, reference, "variant {variant}, blocks of {size}");
+            }
+
+            // scheduled up front, then pulled in uneven calls of different kinds
+            let mut adsr = voice(variant);
+            for &(at, event) in timeline.iter() {
+                adsr.schedule_event(at, event);
+            }
+            let mut out = Vec::new();
+            for len in [1, 7, 300, 2, 90, 1100].into_iter().cycle() {
+                let len = len.min(1500 - out.len());
+                if len == 0 {
+                    break;
+                }
+                if len % 2 == 0 {
+                    out.extend(adsr.fill_next(len));
+                } else {
+                    let mut block = vec![0.0; len];
+                    adsr.fill_block(&mut block);
+                    out.extend(block);
+                }
+            }
+            assert_eq!(out, reference, "variant {variant}, uneven calls");
+        }
+    }
+
+    #[test]
+    fn float_types() {
+        // the curves in f64 hold f64 precision
+        let b = 1.0 / (-(0.4_f32 as f64) * (0.5 - 0.005) + 0.5) - 1.0; // the factor is stored as f32
+        for i in 0..=100 {
+            let x = i as f64 / 100.0;
+            let want = (f64::powf(b, 2.0 * x) - 1.0) / (b * b - 1.0);
+            assert!((CurveKind::Factor(0.4).eval_as(x) - want).abs() < 1e-12);
+            assert!((CurveKind::Cosine.eval_as(x as f32) - CurveKind::Cosine.eval(x as f32)).abs() < 1e-6);
+        }
+        let decay = Segment::new(ADSRPhase::Decay, 1.0, 0.25, 0.5, &CurveKind::Linear);
+        assert!((decay.level_as(0.1_f64) - 0.85).abs() < 1e-12);
+
+        // samples widened for an f64 pipeline
+        let voice = || {
+            let mut adsr = ADSR::new(0.01, 0.02, 0.5, 0.03, 1000.0);
+            adsr.set_param(AttackCurve(0.5));
+            adsr.schedule_event(0, NoteOn);
+            adsr.schedule_event(100, NoteOff);
+            adsr
+        };
+        let mut wide = vec![0.0_f64; 150];
+        voice().fill_block_as(&mut wide);
+        let mut narrow = vec![0.0_f32; 150];
+        voice().fill_block(&mut narrow);
+        assert!(wide.iter().zip(&narrow).all(|(&w, &n)| w == n as f64));
+        let mut adsr = voice();
+        assert!(wide.iter().all(|&w| adsr.generate_as::<f64>() == w));
+    }
+
+    #[test]
+    fn fill_next() {
+        let mut adsr = ADSR::new(0.02, 0.0, 1.0, 0.0, 100.0);
+        adsr.note_on();
+        let audio = [0.5, 0.5, 0.5, 0.5];
+        let out: Vec<f32> = audio.iter().zip(adsr.fill_next(3)).map(|(a, e)| a * e).collect();
+        assert_eq!(out, vec![0.0, 0.25, 0.5]);
+        assert_eq!(adsr.fill_next(2).len(), 2);
+        assert_eq!(adsr.clock(), 3);
+    }
+
+    #[test]
+    fn render() {
+        let mut adsr = ADSR::new(0.0, 0.0, 0.5, 0.0, 100.0);
+        adsr.next();
+        let out = adsr.render(&[(0.01, NoteOn), (0.03, NoteOff), (0.05, NoteOn)], 0.05);
+        assert_eq!(out, vec![0.0, 0.5, 0.5, 0.0, 0.0]);
+        assert_eq!(adsr.next(), 0.0); // the note on at the end was dropped
+
+        let mut adsr = ADSR::new(0.2, 0.3, 0.5, 0.4, 100.0);
+        let out = render_chart("chart/render.png", "render", &mut adsr, 2.0, &[(0.0, NoteOn), (1.0, NoteOff), (1.2, NoteOn), (1.6, NoteOff)]);
+        assert_eq!(out.len(), 200);
+        // peak at the end of the attack, sustain from the end of the decay, the release cut short
+        // by the second note starting over from silence
+        assert_samples(&[out[20], out[50], out[100], out[120], out[140]], &[1.0, 0.5, 0.5, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn advance() {
+        let stepped = |skip: bool| {
+            let mut adsr = ADSR::new(0.1, 0.1, 0.5, 0.1, 100.0);
+            adsr.schedule_event(10, NoteOn);
+            adsr.schedule_event(500, NoteOff);
+            adsr.schedule_event(1000, NoteOn);
+            adsr.trigger_for_samples(30);
+            if skip {
+                adsr.advance(1020);
+            } else {
+                (0..1020).for_each(|_| { adsr.next(); });
+            }
+            (adsr.clock(), adsr.next(), adsr.current_phase())
+        };
+        assert!(stepped(true) == stepped(false));
+    }
+
+    #[test]
+    fn seek() {
+        let played = |release: Option<u64>, samples: u64| {
+            let mut adsr = ADSR::new(0.1, 0.2, 0.5, 0.3, 100.0);
+            adsr.set_param(DecayCurve(0.5));
+            adsr.note_on();
+            if let Some(r) = release {
+                adsr.schedule_event(r, NoteOff);
+            }
+            adsr.advance(samples);
+            (adsr.next(), adsr.next())
+        };
+        let sought = |release: Option<f32>, t: f32| {
+            let mut adsr = ADSR::new(0.1, 0.2, 0.5, 0.3, 100.0);
+            adsr.set_param(DecayCurve(0.5));
+            adsr.seek(t, release);
+            (adsr.next(), adsr.next())
+        };
+        // stepped curves may differ from freshly evaluated ones by rounding
+        let same = |a: (f32, f32), b: (f32, f32)| (a.0 - b.0).abs() < 1e-6 && (a.1 - b.1).abs() < 1e-6;
+        assert!(same(played(None, 5), sought(None, 0.05)));
+        assert!(same(played(None, 20), sought(None, 0.2)));
+        assert!(same(played(Some(20), 30), sought(Some(0.2), 0.3)));
+        assert!(same(played(Some(50), 60), sought(Some(0.5), 0.6)));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn peek() {
+        let mut adsr = ADSR::new(0.03, 0.0, 1.0, 0.0, 100.0);
+        let receiver = adsr.phase_channel(4);
+        adsr.schedule_event(1, NoteOn);
+        assert_eq!(adsr.peek(), 0.0);
+        adsr.next();
+        for _ in 0..4 {
+            let ahead = adsr.peek();
+            assert_eq!(adsr.peek(), ahead);
+            assert_eq!(adsr.next(), ahead);
+        }
+        assert_eq!(receiver.try_iter().count(), 2); // attack and sustain, reported once
+    }
+
+    #[test]
+    fn finite_tail() {
+        let mut adsr = ADSR::new(0.1, 0.2, 0.5, 0.3, 100.0);
+        assert_eq!(adsr.finite_tail().unwrap().len(), 0);
+
+        adsr.trigger_for(0.5);
+        let tail: Vec<f32> = adsr.finite_tail().unwrap().collect();
+        assert_eq!(tail.len(), 81); // gate plus release, then the sample at silence
+        assert!(!adsr.is_active());
+        assert_eq!(tail[80], 0.0);
+
+        adsr.set_mode(EnvelopeMode::OneShot);
+        adsr.note_on();
+        let mut expected = Vec::with_capacity(adsr.finite_tail().unwrap().len());
+        expected.extend(adsr.finite_tail().unwrap());
+        assert_eq!(expected.len(), 31); // attack and decay to zero
+        assert!(!adsr.is_active());
+
+        adsr.set_mode(EnvelopeMode::Gated);
+        adsr.note_off();
+        adsr.next();
+        adsr.note_on();
+        assert!(adsr.finite_tail().is_none());
+    }
+
+    #[test]
+    fn vca() {
+        let mut adsr = ADSR::new(0.02, 0.0, 1.0, 0.0, 100.0);
+        adsr.note_on();
+        let mut mono = [0.5, -0.5, 0.5];
+        adsr.apply(&mut mono);
+        assert_eq!(mono, [0.0, -0.25, 0.5]);
+
+        let mut adsr = ADSR::new(0.02, 0.0, 1.0, 0.0, 100.0);
+        adsr.note_on();
+        let mut stereo = [1.0, -1.0, 1.0, -1.0, 1.0, -1.0];
+        adsr.apply_interleaved(&mut stereo, 2);
+        assert_eq!(stereo, [0.0, 0.0, 0.5, -0.5, 1.0, -1.0]);
+    }
+
+    #[test]
+    fn control_rate() {
+        let render = |period: u32| {
+            let mut adsr = ADSR::new(0.2, 0.3, 0.5, 0.4, 1000.0);
+            adsr.set_param(DecayCurve(0.6));
+            adsr.set_control_period(period);
+            adsr.schedule_event(0, NoteOn);
+            adsr.schedule_event(700, NoteOff);
+            (0..1200).map(|_| adsr.next()).collect::<Vec<f32>>()
+        };
+        let exact = render(1);
+        let approx = render(16);
+        assert!((0..=200).all(|i| (exact[i] - approx[i]).abs() < 1e-5)); // linear attack up to the peak
+        assert!(exact.iter().zip(&approx).all(|(a, b)| (a - b).abs() < 0.005));
+    }
+
+    #[test]
+    fn cached_sample_period() {
+        for sample_rate in [100.0, 44100.0, 48000.0, 96000.0] {
+            let adsr = ADSR::new(0.1, 0.1, 0.5, 0.1, sample_rate);
+            assert!((0..200000).all(|n| to_f32(adsr.seconds(n * TICKS)) == n as f32 / sample_rate));
+            assert!((0..200000).all(|n| to_f32(adsr.seconds(n * TICKS / 4)) == n as f32 / 4.0 / sample_rate));
+        }
+    }
+
+    #[test]
+    fn long_note() {
+        // far past the 2^24 samples where adding 1.0 to an f32 does nothing
+        let mut adsr = ADSR::new(400.0, 0.0, 1.0, 0.0, 48000.0);
+        adsr.seek(360.0, None);
+        let out: Vec<f32> = (0..1000).map(|_| adsr.next()).collect();
+        assert!((out[999] - out[0] - 999.0 / (400.0 * 48000.0)).abs() < 1e-5);
+        assert!((adsr.phase_progress() - (360.0 + 1000.0 / 48000.0) / 400.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn stage_transitions() {
+        let render = |relocate: bool| {
+            let mut adsr = ADSR::new(0.2, 0.3, 0.5, 0.4, 1000.0);
+            adsr.set_param(DelayTime(0.05));
+            adsr.set_param(AttackShape(CurveKind::Bezier(0.2, 0.9)));
+            adsr.set_param(DecayShape(CurveKind::Cosine));
+            adsr.set_param(ReleaseShape(CurveKind::Logarithmic { tension: 3.0 }));
+            adsr.schedule_event(0, NoteOn);
+            adsr.schedule_event(400, NoteOff); // in the middle of the decay
+            adsr.schedule_event(900, NoteOn);
+            adsr.schedule_event(1400, NoteOff);
+            (0..2000).map(|_| {
+                if relocate {
+                    adsr.cursor = None;
+                }
+                let val = adsr.next();
+                (adsr.current_phase as u8, val)
+            }).collect::<Vec<(u8, f32)>>()
+        };
+        assert!(render(false) == render(true));
+    }
+
+    #[test]
+    fn curve_table() {
+        let render = |size: usize| {
+            let mut adsr = ADSR::new(0.2, 0.3, 0.5, 0.4, 1000.0);
+            adsr.set_curve_table(size);
+            adsr.set_param(AttackCurve(-0.8));
+            adsr.set_param(DecayShape(CurveKind::Cosine));
+            adsr.set_param(ReleaseCurve(0.4));
+            adsr.schedule_event(0, NoteOn);
+            adsr.schedule_event(700, NoteOff);
+            (0..1200).map(|_| adsr.next()).collect::<Vec<f32>>()
+        };
+        let exact = render(0);
+        let table = render(256);
+        assert!(exact != table);
+        assert!(exact.iter().zip(&table).all(|(a, b)| (a - b).abs() < 1e-3));
+    }
+
+    #[test]
+    fn fast_math() {
+        let render = |fast: bool| {
+            let mut adsr = ADSR::new(0.2, 0.3, 0.5, 0.4, 1000.0);
+            adsr.set_fast_math(fast);
+            adsr.set_param(AttackShape(CurveKind::SCurve(-0.8)));
+            adsr.set_param(DecayShape(CurveKind::SCurve(0.01)));
+            adsr.set_param(ReleaseCurve(0.4));
+            adsr.schedule_event(0, NoteOn);
+            adsr.schedule_event(700, NoteOff);
+            (0..1200).map(|_| adsr.next()).collect::<Vec<f32>>()
+        };
+        let exact = render(false);
+        let fast = render(true);
+        assert!(exact != fast);
+        assert!(exact.iter().zip(&fast).all(|(a, b)| (a - b).abs() < 1e-4));
+    }
+
+    #[test]
+    fn one_pole() {
+        let render = |one_pole: bool| {
+            let mut adsr = ADSR::new(0.5, 1.0, 0.3, 2.0, 48000.0);
+            adsr.set_param(AttackCurve(-0.5));
+            adsr.set_param(DecayCurve(0.6));
+            adsr.set_param(ReleaseCurve(0.3));
+            adsr.set_one_pole(one_pole);
+            adsr.schedule_event(0, NoteOn);
+            adsr.schedule_event(96000, NoteOff);
+            (0..200000).map(|_| adsr.next()).collect::<Vec<f32>>()
+        };
+        let exact = render(false);
+        let approx = render(true);
+        assert_eq!(approx[24000], 1.0);
+        assert_eq!(approx[96000], 0.3);
+        assert_eq!(approx[200000 - 1], 0.0);
+        assert!(exact.iter().zip(&approx).all(|(a, b)| (a - b).abs() < 1e-3));
+    }
+
+    #[test]
+    #[cfg(feature = "f64")]
+    fn one_pole_f64() {
+        let render = |one_pole: bool| {
+            let mut adsr = ADSR::new(0.5, 1.0, 0.3, 2.0, 48000.0);
+            adsr.set_param(AttackCurve(-0.5));
+            adsr.set_param(DecayCurve(0.6));
+            adsr.set_param(ReleaseCurve(0.3));
+            adsr.set_one_pole(one_pole);
+            adsr.schedule_event(0, NoteOn);
+            adsr.schedule_event(96000, NoteOff);
+            (0..200000).map(|_| adsr.next()).collect::<Vec<f32>>()
+        };
+        let exact = render(false);
+        let approx = render(true);
+        assert!(exact.iter().zip(&approx).all(|(a, b)| (a - b).abs() < 1e-6)); // no drift to speak of
+    }
+
+    #[test]
+    fn flush_tail() {
+        assert_eq!(flush_denormal(1e-12), 0.0);
+        assert_eq!(flush_denormal(-1e-12), 0.0);
+        assert_eq!(flush_denormal(0.001), 0.001);
+        let mut adsr = ADSR::new(0.0, 0.0, 1.0, 1.0, 1000.0);
+        adsr.set_param(ReleaseShape(CurveKind::Exponential { tension: 40.0 })); // lingers near zero
+        adsr.note_on();
+        adsr.next();
+        adsr.note_off();
+        let out: Vec<f32> = (0..1000).map(|_| adsr.next()).collect();
+        assert!(out[500] > 0.0 && out[700] == 0.0); // still releasing, e^-28 below the peak
+        assert!(out.iter().all(|&x| x == 0.0 || x >= FLUSH_LEVEL));
+    }
+
+    #[test]
+    fn scheduled_events() {
+        let mut adsr = ADSR::new(0.0, 0.0, 0.5, 0.0, 100.0);
+        adsr.schedule_event(3, NoteOff);
+        adsr.schedule_event(1, NoteOn);
+        adsr.schedule_event(3, NoteOn); // same sample, applied after the note off
+        adsr.schedule_event(5, NoteOff);
+        let out: Vec<f32> = (0..6).map(|_| adsr.next()).collect();
+        assert_eq!(out, vec![0.0, 0.5, 0.5, 0.5, 0.5, 0.0]);
+        assert_eq!(adsr.clock(), 6);
+        adsr.schedule_event(2, NoteOn); // already past
+        assert_eq!(adsr.next(), 0.5);
+    }
+
+    // counts the allocations of the current thread, so tests running alongside do not interfere
+    #[cfg(feature = "std")]
+    struct CountingAllocator;
+
+    #[cfg(feature = "std")]
+    thread_local! {
+        static ALLOCATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    #[cfg(feature = "std")]
+    fn allocations() -> usize {
+        ALLOCATIONS.with(|n| n.get())
+    }
+
+    #[cfg(feature = "std")]
+    unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+            unsafe { std::alloc::System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            unsafe { std::alloc::System.dealloc(ptr, layout) }
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: std::alloc::Layout, new_size: usize) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+            unsafe { std::alloc::System.realloc(ptr, layout, new_size) }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn realtime_safe() {
+        let mut adsr = ADSR::new(0.01, 0.02, 0.5, 0.03, 1000.0);
+        adsr.set_curve_table(64);
+        adsr.set_param_smoothing(0.005);
+        adsr.set_param_crossfade(0.005);
+        adsr.set_realtime_safe(true);
+        let mut out = vec![0.0; 400];
+        let before = allocations();
+
+        adsr.set_param(AttackTime(f32::NAN)); // ignored
+        adsr.set_param(AttackCurve(3.0));     // glides to 1.0, refilling the table in place
+        adsr.set_param(SustainLevel(2.0));
+        adsr.set_key(200);
+        adsr.set_depth(f32::NAN);
+        adsr.set_sustain_pedal_level(-0.5);
+        adsr.set_next_event(NoteOnWithVelocity(1.5));
+        for _ in 0..REALTIME_EVENTS + 10 {
+            adsr.schedule_event(adsr.clock() + 300, NoteOff);
+        }
+        let ahead = adsr.peek(); // gliding, with a full queue
+        adsr.fill_block(&mut out[..50]);
+        assert_eq!(out[0], ahead);
+        adsr.set_param_smoothing(0.0);
+        adsr.set_param(SustainLevel(-1.0)); // crossfades to the new sustain
+        adsr.set_param(DecayCurve(f32::INFINITY));
+        adsr.set_param(ReleaseTime(-1.0));
+        adsr.process_block(&mut out[50..150], &[(90, NoteOff), (10, NoteOnWithVelocity(f32::NAN))]);
+        adsr.set_sustain_pedal_level(f32::NAN);
+        adsr.trigger_for(-1.0);
+        adsr.fill_block(&mut out[150..]);
+        assert_eq!(allocations(), before);
+
+        assert!(out.iter().all(|x| (0.0..=1.0).contains(x)));
+        assert_eq!(out[49], 1.0); // sustain clamped to 1.0 by then
+        assert_eq!(out[399], 0.0);
+        assert_eq!(adsr.key, 127);
+        assert_eq!(adsr.depth, 1.0);
+    }
+
+    #[test]
+    fn realtime_queue_full() {
+        let mut adsr = ADSR::new(0.0, 0.0, 1.0, 0.0, 100.0);
+        adsr.set_realtime_safe(true);
+        adsr.note_on();
+        adsr.next();
+        while adsr.schedule_event(50, NoteOn) {}
+        assert!(!adsr.schedule_event(20, NoteOff)); // later events are dropped
+        assert_eq!(adsr.dropped_events(), 2);
+        adsr.note_off(); // due now, applied anyway
+        adsr.set_sustain_pedal(true);
+        assert_eq!(adsr.next(), 0.0);
+        assert!(adsr.scheduled.iter().all(|&(at, _)| at == 50));
     }
 }
@@ -0,0 +1,232 @@
+use crate::{ADSREvent, ADSR};
+use midly::live::LiveEvent;
+use midly::MidiMessage;
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)] // std's own float methods take over when tests link std
+use num_traits::Float;
+
+const SUSTAIN_PEDAL: u8 = 64;
+const ALL_SOUND_OFF: u8 = 120;
+const ALL_NOTES_OFF: u8 = 123;
+const CONTROL_CHANGE: u8 = 0xB0;
+
+// glue for driving one monophonic envelope from MIDI on any channel. a note off only closes
+// the gate for the key that was played last, so overlapping notes behave like a mono synth
+impl ADSR {
+    // a complete message, status byte first. returns false if it was not understood or not relevant
+    pub fn handle_midi_bytes(&mut self, bytes: &[u8]) -> bool {
+        match LiveEvent::parse(bytes) {
+            Ok(event) => self.handle_midi(event),
+            Err(_) => false,
+        }
+    }
+
+    pub fn handle_midi(&mut self, event: LiveEvent) -> bool {
+        let LiveEvent::Midi { message, .. } = event else {
+            return false;
+        };
+        match message {
+            MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                self.set_key(key.as_int());
+                self.set_next_event(ADSREvent::NoteOnWithVelocity(vel.as_int() as f32 / 127.0));
+            },
+            MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                if key.as_int() != self.next_key {
+                    return false;
+                }
+                self.note_off();
+            },
+            MidiMessage::Controller { controller, value } if controller == SUSTAIN_PEDAL => {
+                self.set_sustain_pedal_level(value.as_int() as f32 / 127.0);
+            },
+            MidiMessage::Controller { controller, .. } if controller == ALL_SOUND_OFF => {
+                self.panic(true);
+            },
+            MidiMessage::Controller { controller, .. } if controller == ALL_NOTES_OFF => {
+                self.note_off();
+            },
+            _ => {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// 7-bit on one controller, or 14-bit with the most significant 7 bits on the controller and the
+// least significant on the controller 32 above it, as CC 1 and 33 carry a fine modulation wheel
+#[derive(Copy, Clone, PartialEq)]
+pub enum CcResolution {
+    Coarse,
+    Fine,
+}
+
+// an envelope as a stream of control change messages for external gear. the output is read at
+// a rate of messages per second, 0..1 to the full range of the controller (clamped, so leave
+// the output range and polarity at their defaults), and sent only when it has moved by at least
+// the minimum delta since the last message, or reached either end
+pub struct CcOutput {
+    status: u8,
+    controller: u8,
+    resolution: CcResolution,
+    interval: u32, // samples between readings
+    countdown: u32,
+    min_delta: u16,
+    sent: Option<u16>,
+    messages: [[u8; 3]; 2],
+    len: usize,
+}
+
+impl CcOutput {
+    // channel 0..16, controller 0..32 for Fine and 0..120 for Coarse
+    pub fn new(channel: u8, controller: u8, resolution: CcResolution, rate: f32, sample_rate: f32) -> Self {
+        assert!(channel < 16);
+        assert!(controller < if resolution == CcResolution::Fine { 32 } else { ALL_SOUND_OFF });
+        assert!(rate > 0.0 && rate.is_finite());
+        CcOutput {
+            status: CONTROL_CHANGE | channel,
+            controller,
+            resolution,
+            interval: (sample_rate / rate).round().max(1.0) as u32,
+            countdown: 0,
+            min_delta: 1,
+            sent: None,
+            messages: [[0; 3]; 2],
+            len: 0,
+        }
+    }
+
+    // in steps of the resolution, 1 sends every change
+    pub fn set_min_delta(&mut self, steps: u16) {
+        assert!(steps >= 1);
+        self.min_delta = steps;
+    }
+
+    // send the next reading whatever the last message was, e.g. after the receiver was replaced
+    pub fn resend(&mut self) {
+        self.sent = None;
+    }
+
+    // one sample of adsr, with the messages due at it
+    pub fn process(&mut self, adsr: &mut ADSR) -> &[[u8; 3]] {
+        let value = adsr.generate();
+        self.feed(value)
+    }
+
+    // len samples of adsr, each message passed to send with its offset in the block
+    pub fn process_block(&mut self, adsr: &mut ADSR, len: usize, mut send: impl FnMut(usize, [u8; 3])) {
+        let mut chunk = [0.0; 64];
+        let mut offset = 0;
+        while offset < len {
+            let chunk = &mut chunk[..(len - offset).min(64)];
+            adsr.fill_block(chunk);
+            for &value in chunk.iter() {
+                for &message in self.feed(value) {
+                    send(offset, message);
+                }
+                offset += 1;
+            }
+        }
+    }
+
+    // a value from elsewhere, one per sample
+    pub fn feed(&mut self, value: f32) -> &[[u8; 3]] {
+        self.len = 0;
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            return &[];
+        }
+        self.countdown = self.interval - 1;
+        let max = if self.resolution == CcResolution::Fine { 0x3fff } else { 0x7f };
+        let step = (value.clamp(0.0, 1.0) * max as f32).round() as u16;
+        let moved = match self.sent {
+            Some(sent) => sent.abs_diff(step) >= self.min_delta || (step != sent && (step == 0 || step == max)),
+            None => true,
+        };
+        if !moved {
+            return &[];
+        }
+        let (status, controller) = (self.status, self.controller);
+        match self.resolution {
+            CcResolution::Coarse => self.push([status, controller, step as u8]),
+            CcResolution::Fine => {
+                // the receiver clears the low bits on a new high byte, so that goes first
+                if self.sent.is_none_or(|sent| sent >> 7 != step >> 7) {
+                    self.push([status, controller, (step >> 7) as u8]);
+                }
+                self.push([status, controller + 32, (step & 0x7f) as u8]);
+            },
+        }
+        self.sent = Some(step);
+        &self.messages[..self.len]
+    }
+
+    fn push(&mut self, message: [u8; 3]) {
+        self.messages[self.len] = message;
+        self.len += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ADSRParamKind;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn midi_messages() {
+        let mut adsr = ADSR::new(0.0, 0.0, 1.0, 0.0, 100.0);
+        assert!(adsr.handle_midi_bytes(&[0x90, 60, 127]));
+        assert_eq!(adsr.generate(), 1.0);
+        assert!(adsr.handle_midi_bytes(&[0x91, 64, 127]));
+        assert!(!adsr.handle_midi_bytes(&[0x80, 60, 0])); // no longer the sounding key
+        assert_eq!(adsr.generate(), 1.0);
+        assert!(adsr.handle_midi_bytes(&[0xB0, 64, 127]));
+        assert!(adsr.handle_midi_bytes(&[0x90, 64, 0]));
+        assert_eq!(adsr.generate(), 1.0); // held by the pedal
+        assert!(adsr.handle_midi_bytes(&[0xB0, 64, 0]));
+        assert_eq!(adsr.generate(), 0.0);
+        assert!(adsr.handle_midi_bytes(&[0x90, 62, 127]));
+        assert!(adsr.handle_midi_bytes(&[0xB0, 123, 0]));
+        assert_eq!(adsr.generate(), 0.0);
+        assert!(adsr.handle_midi_bytes(&[0x90, 62, 127]));
+        assert_eq!(adsr.generate(), 1.0);
+        assert!(adsr.handle_midi_bytes(&[0xB0, 120, 0]));
+        assert!(adsr.generate() < 1.0);
+        assert!(!adsr.handle_midi_bytes(&[0xE0, 0, 64]));
+        assert!(!adsr.handle_midi_bytes(&[0x90]));
+    }
+
+    #[test]
+    fn cc_output() {
+        let mut adsr = ADSR::new(0.1, 0.0, 0.5, 0.1, 1000.0);
+        adsr.set_param(ADSRParamKind::AttackCurve(0.0));
+        adsr.set_param(ADSRParamKind::ReleaseCurve(0.0));
+        let mut cc = CcOutput::new(2, 74, CcResolution::Coarse, 100.0, 1000.0);
+        let mut sent = Vec::new();
+        adsr.note_on();
+        cc.process_block(&mut adsr, 300, |offset, message| sent.push((offset, message)));
+        assert_eq!(sent[0], (0, [0xB2, 74, 0]));
+        assert!(sent.iter().all(|&(offset, _)| offset % 10 == 0)); // a reading every 10 samples
+        assert_eq!(sent.last().unwrap().1, [0xB2, 74, 64]); // and none while the sustain holds
+        assert_eq!(sent.len(), 11);
+
+        // a coarser delta thins out the release, which still settles at 0
+        sent.clear();
+        cc.set_min_delta(20);
+        adsr.note_off();
+        cc.process_block(&mut adsr, 200, |offset, message| sent.push((offset, message)));
+        let values: Vec<u8> = sent.iter().map(|&(_, message)| message[2]).collect();
+        assert_eq!(values, vec![44, 19, 0]);
+    }
+
+    #[test]
+    fn cc_output_fine() {
+        let mut cc = CcOutput::new(0, 1, CcResolution::Fine, 1000.0, 1000.0);
+        assert_eq!(cc.feed(0.5), &[[0xB0, 1, 0x40], [0xB0, 33, 0]]);
+        assert!(cc.feed(0.5).is_empty());
+        assert_eq!(cc.feed(0.501), &[[0xB0, 33, 16]]); // only the low bits moved
+        assert_eq!(cc.feed(1.0), &[[0xB0, 1, 0x7f], [0xB0, 33, 0x7f]]);
+    }
+}
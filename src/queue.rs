@@ -0,0 +1,172 @@
+use crate::{ADSREvent, ADSR};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+// 32-bit words only, for targets without 64-bit atomics such as Cortex-M3. the halves need no
+// atomicity between them: a slot is only written while the consumer cannot see it, and tail
+// publishes it whole
+struct Ring {
+    slots: Box<[[AtomicU32; 4]]>, // sample low and high, encoded event, velocity bits
+    head: AtomicUsize, // count of events popped, wrapping
+    tail: AtomicUsize, // count of events pushed, wrapping
+}
+
+// a fixed size queue of timed events from one thread (e.g. MIDI input) to another (the audio
+// thread). neither end allocates, locks or waits. the capacity is rounded up to a power of two,
+// so the slot index stays in step when the counters wrap, which 32-bit targets reach
+pub fn event_queue(capacity: usize) -> (EventProducer, EventConsumer) {
+    counted_from(capacity, 0)
+}
+
+fn counted_from(capacity: usize, count: usize) -> (EventProducer, EventConsumer) {
+    assert!(capacity > 0);
+    let ring = Arc::new(Ring {
+        slots: (0..capacity.next_power_of_two()).map(|_| core::array::from_fn(|_| AtomicU32::new(0))).collect(),
+        head: AtomicUsize::new(count),
+        tail: AtomicUsize::new(count),
+    });
+    (EventProducer { ring: Arc::clone(&ring) }, EventConsumer { ring })
+}
+
+impl Ring {
+    fn slot(&self, count: usize) -> &[AtomicU32; 4] {
+        &self.slots[count & (self.slots.len() - 1)]
+    }
+}
+
+pub struct EventProducer {
+    ring: Arc<Ring>,
+}
+
+pub struct EventConsumer {
+    ring: Arc<Ring>,
+}
+
+impl EventProducer {
+    // event for the sample at_sample of the receiving envelope's clock(). false, dropping the
+    // event, while the queue is full
+    pub fn push(&mut self, at_sample: u64, event: ADSREvent) -> bool {
+        let ring = &self.ring;
+        let tail = ring.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(ring.head.load(Ordering::Acquire)) == ring.slots.len() {
+            return false;
+        }
+        let slot = ring.slot(tail);
+        let (tag, velocity) = encode(event);
+        slot[0].store(at_sample as u32, Ordering::Relaxed);
+        slot[1].store((at_sample >> 32) as u32, Ordering::Relaxed);
+        slot[2].store(tag, Ordering::Relaxed);
+        slot[3].store(velocity, Ordering::Relaxed);
+        ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        true
+    }
+}
+
+impl EventConsumer {
+    pub fn pop(&mut self) -> Option<(u64, ADSREvent)> {
+        let ring = &self.ring;
+        let head = ring.head.load(Ordering::Relaxed);
+        if head == ring.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let slot = ring.slot(head);
+        let [low, high, tag, velocity] = slot.each_ref().map(|word| word.load(Ordering::Relaxed));
+        let item = ((high as u64) << 32 | low as u64, decode(tag, velocity));
+        ring.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(item)
+    }
+
+    // schedule everything queued so far on adsr, usually at the start of each block
+    pub fn schedule_into(&mut self, adsr: &mut ADSR) {
+        while let Some((at, event)) = self.pop() {
+            adsr.schedule_event(at, event);
+        }
+    }
+}
+
+fn encode(event: ADSREvent) -> (u32, u32) {
+    match event {
+        ADSREvent::NoteOn => (0, 0),
+        ADSREvent::NoteOff => (1, 0),
+        ADSREvent::Retrigger => (2, 0),
+        ADSREvent::NoteOnWithVelocity(v) => (3, v.to_bits()),
+    }
+}
+
+fn decode(tag: u32, velocity: u32) -> ADSREvent {
+    match tag {
+        0 => ADSREvent::NoteOn,
+        1 => ADSREvent::NoteOff,
+        2 => ADSREvent::Retrigger,
+        _ => ADSREvent::NoteOnWithVelocity(f32::from_bits(velocity)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn spsc_events() {
+        let (mut producer, mut consumer) = event_queue(2);
+        assert!(producer.push(1, ADSREvent::NoteOnWithVelocity(0.5)));
+        assert!(producer.push(3, ADSREvent::NoteOff));
+        assert!(!producer.push(4, ADSREvent::NoteOn)); // full
+        assert!(consumer.pop() == Some((1, ADSREvent::NoteOnWithVelocity(0.5))));
+        assert!(producer.push(5, ADSREvent::Retrigger));
+
+        let midi = std::thread::spawn(move || {
+            let mut sent = 0;
+            while sent < 100 {
+                if producer.push(10 + sent, ADSREvent::NoteOn) {
+                    sent += 1;
+                }
+            }
+        });
+        let mut received = Vec::new();
+        while received.len() < 102 {
+            if let Some((at, _)) = consumer.pop() {
+                received.push(at);
+            }
+        }
+        midi.join().unwrap();
+        assert_eq!(received[..2], [3, 5]);
+        assert!(received[2..].iter().copied().eq(10..110));
+    }
+
+    #[test]
+    fn wide_samples() {
+        let (mut producer, mut consumer) = event_queue(1);
+        assert!(producer.push(5 << 32 | 7, ADSREvent::NoteOnWithVelocity(0.25)));
+        assert!(consumer.pop() == Some((5 << 32 | 7, ADSREvent::NoteOnWithVelocity(0.25))));
+    }
+
+    #[test]
+    fn counters_wrap() {
+        let (mut producer, mut consumer) = counted_from(3, usize::MAX - 2);
+        for round in 0..3 {
+            for at in 0..4 {
+                assert!(producer.push(round * 4 + at, ADSREvent::NoteOff));
+            }
+            assert!(!producer.push(99, ADSREvent::NoteOn)); // 3 rounded up to 4
+            for at in 0..4 {
+                assert!(consumer.pop() == Some((round * 4 + at, ADSREvent::NoteOff)));
+            }
+            assert!(consumer.pop().is_none());
+        }
+    }
+
+    #[test]
+    fn schedule_into() {
+        let (mut producer, mut consumer) = event_queue(4);
+        let mut adsr = ADSR::new(0.0, 0.0, 1.0, 0.0, 100.0);
+        producer.push(2, ADSREvent::NoteOn);
+        consumer.schedule_into(&mut adsr);
+        let out: Vec<f32> = (0..3).map(|_| adsr.generate()).collect();
+        assert_eq!(out, vec![0.0, 0.0, 1.0]);
+    }
+}
@@ -0,0 +1,95 @@
+use crate::{ADSRParams, CurveKind};
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)] // std's own float methods take over when tests link std
+use num_traits::Float;
+
+// full scale of the volume envelope in centibels, a decay or release over this range takes the whole stage time
+const FULL_SCALE_CB: f32 = 1000.0;
+
+// absolute timecents to seconds, -32768 meaning instantaneous
+fn timecents_to_secs(tc: i32, min: i32, max: i32) -> f32 {
+    if tc <= -32768 {
+        0.0
+    } else {
+        2.0_f32.powf(tc.clamp(min, max) as f32 / 1200.0)
+    }
+}
+
+// centibels of attenuation to amplitude
+fn centibels_to_gain(cb: f32) -> f32 {
+    10.0_f32.powf(-cb / 200.0)
+}
+
+impl ADSRParams {
+    // volume envelope generators of a SoundFont 2 zone (delayVolEnv, attackVolEnv, holdVolEnv,
+    // decayVolEnv, sustainVolEnv, releaseVolEnv), clamped to the ranges of the specification.
+    // attack is linear in amplitude while decay and release are linear in decibels
+    pub fn from_sf2(delay_tc: i32, attack_tc: i32, hold_tc: i32, decay_tc: i32, sustain_cb: i32, release_tc: i32) -> Self {
+        let sustain_cb = sustain_cb.clamp(0, 1440) as f32;
+        let sustain_level = centibels_to_gain(sustain_cb);
+
+        // the decay time is given for the full scale, the stage only runs down to the sustain attenuation
+        let decay_time = timecents_to_secs(decay_tc, -12000, 8000) * (sustain_cb / FULL_SCALE_CB).min(1.0);
+        // so is the release time, which from the sustain attenuation has only the rest of the scale to go.
+        // a note released before its decay ends falls the same time from higher up
+        let release_cb = (FULL_SCALE_CB - sustain_cb).max(0.0);
+        let release_time = timecents_to_secs(release_tc, -12000, 8000) * release_cb / FULL_SCALE_CB;
+
+        let mut params = ADSRParams::new(
+            timecents_to_secs(attack_tc, -12000, 8000), decay_time, sustain_level, release_time, 0.0, 0.0, 0.0
+        );
+        params.delay_time = timecents_to_secs(delay_tc, -12000, 5000);
+        params.hold_time = timecents_to_secs(hold_tc, -12000, 5000);
+
+        // a segment falling by a factor g is linear in decibels when shaped by e^(k*x) with k = ln(1/g)
+        params.attack_curve = CurveKind::Linear;
+        params.decay_curve = CurveKind::Exponential { tension: -sustain_level.ln() };
+        params.release_curve = CurveKind::Exponential { tension: -centibels_to_gain(release_cb).ln() };
+        params
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ADSREvent, ADSR};
+    use alloc::vec::Vec;
+
+    #[test]
+    fn sf2_conversion() {
+        let params = ADSRParams::from_sf2(-32768, 0, 1200, 0, 200, -12000);
+        assert_eq!(params.delay_time, 0.0);
+        assert_eq!(params.attack_time, 1.0);
+        assert_eq!(params.hold_time, 2.0);
+        assert!((params.sustain_level - 0.1).abs() < 1e-6);
+        assert!((params.decay_time - 0.2).abs() < 1e-6);
+        assert!((params.release_time - 0.000_781_3).abs() < 1e-6); // 800 of the 1000 cB
+
+        // decibel-linear decay: halfway through, the level is halfway in decibels
+        let x = params.decay_curve.eval(0.5);
+        let level = params.sustain_level + (1.0 - params.sustain_level) * x;
+        assert!((level - 10.0_f32.powf(-0.5)).abs() < 1e-4);
+
+        let mut adsr = ADSR::with_params(params, 100.0);
+        adsr.set_next_event(ADSREvent::NoteOn);
+        let out: Vec<f32> = (0..400).map(|_| adsr.generate()).collect();
+        assert!((out[50] - 0.5).abs() < 1e-6);
+        assert!((out[399] - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn release_from_sustain() {
+        // 2 s for the full 100 dB, of which 80 dB are left below a sustain at -20 dB
+        let params = ADSRParams::from_sf2(-32768, -32768, -32768, -32768, 200, 1200);
+        assert!((params.release_time - 1.6).abs() < 1e-6);
+
+        let mut adsr = ADSR::with_params(params, 100.0);
+        adsr.set_next_event(ADSREvent::NoteOn);
+        assert!((adsr.generate() - 0.1).abs() < 1e-6);
+        adsr.set_next_event(ADSREvent::NoteOff);
+        let out: Vec<f32> = (0..=160).map(|_| adsr.generate()).collect();
+        // halfway down in decibels, at -60 dB, halfway through
+        assert!((out[80] / 0.001 - 1.0).abs() < 0.02);
+        assert_eq!(out[160], 0.0);
+    }
+}
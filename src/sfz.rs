@@ -0,0 +1,67 @@
+use crate::ADSRParams;
+
+const STAGES: [&str; 6] = ["delay", "attack", "hold", "decay", "sustain", "release"];
+
+impl ADSRParams {
+    // amplitude envelope opcodes of an SFZ region, already parsed to numbers, at a note velocity of 0..=127.
+    // ampeg_<stage> sets a stage (times in seconds, sustain in percent) and ampeg_vel2<stage>
+    // (or ampeg_<stage>_veltrack) adds its value scaled by velocity / 127. other opcodes are ignored
+    pub fn from_sfz(opcodes: &[(&str, f32)], velocity: u8) -> Self {
+        // delay, attack, hold, decay, sustain, release with the SFZ defaults
+        let mut base = [0.0, 0.0, 0.0, 0.0, 100.0, 0.0];
+        let mut veltrack = [0.0; 6];
+        for &(opcode, value) in opcodes {
+            let Some(name) = opcode.strip_prefix("ampeg_") else {
+                continue;
+            };
+            for (i, stage) in STAGES.iter().enumerate() {
+                if name == *stage {
+                    base[i] = value;
+                } else if name.strip_prefix("vel2") == Some(stage) || name.strip_suffix("_veltrack") == Some(stage) {
+                    veltrack[i] = value;
+                }
+            }
+        }
+
+        let amount = velocity.min(127) as f32 / 127.0;
+        let value = |i: usize, max: f32| (base[i].clamp(0.0, max) + veltrack[i] * amount).clamp(0.0, max);
+
+        let mut params = ADSRParams::new(
+            value(1, 100.0), value(3, 100.0), value(4, 100.0) / 100.0, value(5, 100.0),
+            0.0, 0.0, 0.0
+        );
+        params.delay_time = value(0, 100.0);
+        params.hold_time = value(2, 100.0);
+        params
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sfz_conversion() {
+        let opcodes = [
+            ("ampeg_attack", 0.01),
+            ("ampeg_vel2attack", -0.01),
+            ("ampeg_hold", 0.2),
+            ("ampeg_sustain", 40.0),
+            ("ampeg_sustain_veltrack", 50.0),
+            ("ampeg_release", 0.5),
+            ("pitch_keycenter", 60.0),
+        ];
+        let soft = ADSRParams::from_sfz(&opcodes, 0);
+        assert_eq!(soft.attack_time, 0.01);
+        assert_eq!(soft.hold_time, 0.2);
+        assert!((soft.sustain_level - 0.4).abs() < 1e-6);
+        assert_eq!(soft.release_time, 0.5);
+        let hard = ADSRParams::from_sfz(&opcodes, 127);
+        assert_eq!(hard.attack_time, 0.0);
+        assert!((hard.sustain_level - 0.9).abs() < 1e-6);
+
+        let defaults = ADSRParams::from_sfz(&[], 100);
+        assert_eq!(defaults.sustain_level, 1.0);
+        assert_eq!(defaults.release_time, 0.0);
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{ADSREvent, ADSRParamKind, ADSRParams, ADSR};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+// voices playing one set of parameters: a knob turn edits the parameters once and every
+// voice follows it at its next sample, without each voice keeping and updating its own copy
+pub struct SharedParams {
+    params: Arc<ADSRParams>,
+    voices: Vec<ADSR>,
+}
+
+impl SharedParams {
+    pub fn new(params: ADSRParams, voices: usize, sample_rate: f32) -> Self {
+        let params = Arc::new(params);
+        SharedParams {
+            voices: (0..voices).map(|_| ADSR::with_shared_params(Arc::clone(&params), sample_rate)).collect(),
+            params,
+        }
+    }
+
+    pub fn params(&self) -> &ADSRParams {
+        &self.params
+    }
+
+    // the parameters are copied once if a voice still holds the old ones, then handed to all voices
+    pub fn set_param(&mut self, param: ADSRParamKind) {
+        Arc::make_mut(&mut self.params).set_param(param);
+        for adsr in self.voices.iter_mut() {
+            adsr.set_shared_params(Arc::clone(&self.params));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.voices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.voices.is_empty()
+    }
+
+    // a voice changing its own parameters with ADSR::set_param() stops following the shared
+    // ones until the next SharedParams::set_param()
+    pub fn voice(&self, index: usize) -> &ADSR {
+        &self.voices[index]
+    }
+
+    pub fn voice_mut(&mut self, index: usize) -> &mut ADSR {
+        &mut self.voices[index]
+    }
+
+    pub fn set_next_event(&mut self, index: usize, event: ADSREvent) {
+        self.voices[index].set_next_event(event);
+    }
+
+    // one sample of every voice
+    pub fn generate(&mut self, out: &mut [f32]) {
+        assert_eq!(out.len(), self.voices.len());
+        for (sample, adsr) in out.iter_mut().zip(self.voices.iter_mut()) {
+            *sample = adsr.generate();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_params() {
+        let mut voices = SharedParams::new(ADSRParams::new(0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0), 3, 100.0);
+        let mut out = [0.0; 3];
+        voices.set_next_event(0, ADSREvent::NoteOn);
+        voices.set_next_event(2, ADSREvent::NoteOn);
+        voices.generate(&mut out);
+        assert_eq!(out, [1.0, 0.0, 1.0]);
+
+        voices.set_param(ADSRParamKind::SustainLevel(0.5));
+        voices.generate(&mut out);
+        assert_eq!(out, [0.5, 0.0, 0.5]);
+        assert_eq!(voices.params().sustain_level, 0.5);
+        // the voices hold the shared parameters, nothing was copied per voice
+        assert_eq!(Arc::strong_count(&voices.params), 4);
+
+        voices.voice_mut(2).set_param(ADSRParamKind::SustainLevel(0.25));
+        voices.generate(&mut out);
+        assert_eq!(out, [0.5, 0.0, 0.25]);
+        voices.set_param(ADSRParamKind::SustainLevel(0.75));
+        voices.generate(&mut out);
+        assert_eq!(out, [0.75, 0.0, 0.75]);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{cast, to_f32, ADSRPhase, CurveKind, Float, Real};
+
+static FLAT: CurveKind = CurveKind::Factor(0.0);
+
+// one segment of an envelope: where it heads, for how long, with which shape, and when it is done.
+// ADSR, BreakpointEnvelope and AdsrFixed play sequences of Segment, located through the same
+// is_complete() and duration() that locate() walks a sequence of stages of your own with
+pub trait Stage {
+    fn phase(&self) -> ADSRPhase;
+
+    // seconds, f32::INFINITY for stages that last until the gate changes
+    fn duration(&self) -> f32;
+
+    // level reached when the stage completes
+    fn target(&self) -> f32;
+
+    // level t seconds after entering the stage
+    fn level(&self, t: f32) -> f32;
+
+    fn is_complete(&self, t: f32) -> bool {
+        t >= self.duration()
+    }
+}
+
+// moves from one level to another, the curve describing the shape relative to the lower end
+#[derive(Clone)]
+pub struct Segment<'a> {
+    phase: ADSRPhase,
+    from: f32,
+    to: f32,
+    duration: f32,
+    curve: &'a CurveKind,
+    fast: bool,
+}
+
+impl<'a> Segment<'a> {
+    pub fn new(phase: ADSRPhase, from: f32, to: f32, duration: f32, curve: &'a CurveKind) -> Self {
+        Segment { phase, from, to, duration, curve, fast: false }
+    }
+
+    // stays at one level
+    pub fn flat(phase: ADSRPhase, level: f32, duration: f32) -> Self {
+        Segment { phase, from: level, to: level, duration, curve: &FLAT, fast: false }
+    }
+
+    // evaluate the curve with the approximations of ADSRParams::set_fast_math()
+    pub(crate) fn fast_math(self, fast: bool) -> Self {
+        Segment { fast, ..self }
+    }
+
+    pub(crate) fn from(&self) -> f32 {
+        self.from
+    }
+
+    pub(crate) fn curve(&self) -> &CurveKind {
+        self.curve
+    }
+
+    // level t seconds after entering the stage, in the internal precision. the ends are exactly
+    // from and to whatever the curve gives there, so a stage picks up where the previous one left
+    pub(crate) fn level_real<T: Float>(&self, t: T) -> T {
+        let (from, to, duration) = (cast::<T>(self.from), cast::<T>(self.to), cast::<T>(self.duration));
+        if from == to || self.duration.is_infinite() || t >= duration {
+            return to;
+        }
+        if t <= T::zero() {
+            return from;
+        }
+        let x = (t / duration).clamp(T::zero(), T::one());
+        if to >= from {
+            from + (to - from) * self.curve.eval_real(x, self.fast)
+        } else {
+            to + (from - to) * self.curve.eval_real(T::one() - x, self.fast)
+        }
+    }
+
+    // level in the precision of T, e.g. to plot or analyse a stage in f64
+    pub fn level_as<T: Float>(&self, t: T) -> T {
+        self.level_real(t)
+    }
+}
+
+impl Stage for Segment<'_> {
+    fn phase(&self) -> ADSRPhase {
+        self.phase
+    }
+
+    fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    fn target(&self) -> f32 {
+        self.to
+    }
+
+    fn level(&self, t: f32) -> f32 {
+        to_f32(self.level_real(t as Real))
+    }
+}
+
+// find the stage running t seconds into a sequence, along with the time spent inside it
+pub fn locate<S: Stage>(stages: &[S], t: f32) -> Option<(&S, f32)> {
+    locate_index(stages, t as Real).map(|(i, t)| (&stages[i], to_f32(t)))
+}
+
+// like locate in the internal precision, with the position of the stage in the sequence.
+// stages end where they would in f32, the precision their durations come in
+pub(crate) fn locate_index<S: Stage>(stages: &[S], t: Real) -> Option<(usize, Real)> {
+    let mut start = 0.0;
+    for (i, stage) in stages.iter().enumerate() {
+        if !stage.is_complete(to_f32(t - start)) {
+            return Some((i, t - start));
+        }
+        start += stage.duration() as Real;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a stage that ends as soon as it reaches its target, whatever the time
+    struct Ramp {
+        rate: f32,
+    }
+
+    impl Stage for Ramp {
+        fn phase(&self) -> ADSRPhase {
+            ADSRPhase::Attack
+        }
+
+        fn duration(&self) -> f32 {
+            1.0 / self.rate
+        }
+
+        fn target(&self) -> f32 {
+            1.0
+        }
+
+        fn level(&self, t: f32) -> f32 {
+            (t * self.rate).min(1.0)
+        }
+
+        fn is_complete(&self, t: f32) -> bool {
+            self.level(t) >= self.target()
+        }
+    }
+
+    #[test]
+    fn locate_in_sequence() {
+        let exp = CurveKind::Factor(0.5);
+        let stages = [
+            Segment::flat(ADSRPhase::Delay, 0.0, 0.5),
+            Segment::new(ADSRPhase::Attack, 0.0, 1.0, 1.0, &exp),
+            Segment::new(ADSRPhase::Decay, 1.0, 0.5, 0.0, &exp),
+            Segment::flat(ADSRPhase::Sustain, 0.5, f32::INFINITY),
+        ];
+        let (stage, t) = locate(&stages, 0.2).unwrap();
+        assert!(stage.phase() == ADSRPhase::Delay && t == 0.2);
+        let (stage, t) = locate(&stages, 1.0).unwrap();
+        assert!(stage.phase() == ADSRPhase::Attack && t == 0.5);
+        let (stage, _) = locate(&stages, 1.5).unwrap();
+        assert!(stage.phase() == ADSRPhase::Sustain && stage.level(100.0) == 0.5);
+    }
+
+    #[test]
+    fn user_defined_stage() {
+        let stages = [Ramp { rate: 4.0 }];
+        let (stage, t) = locate(&stages, 0.125).unwrap();
+        assert_eq!(stage.level(t), 0.5);
+        assert!(locate(&stages, 0.25).is_none());
+    }
+}
@@ -0,0 +1,241 @@
+use crate::{ADSREvent, ADSRParams, ADSRPhase, ADSRState, EnvelopeMode, Stage, ADSR, TICKS};
+use alloc::sync::Arc;
+use core::fmt;
+
+// which invariant a sample broke
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Invariant {
+    NotFinite,      // NaN or infinite, generate() silenced the voice
+    OutOfRange,     // below 0 or above 1 before the output stage
+    AttackReversed, // the attack turned back toward where it started
+    PhaseOrder,     // a phase the one before cannot lead to
+}
+
+// the first sample that broke an invariant, with what it was generated from. state is taken
+// right after the sample, so restore_state() on a voice with these parameters replays from there
+#[derive(Clone)]
+pub struct Violation {
+    pub clock: u64, // clock() of the sample
+    pub invariant: Invariant,
+    pub value: f32, // before the output stage
+    pub previous: f32,
+    pub phase: ADSRPhase,
+    pub previous_phase: ADSRPhase,
+    pub state: ADSRState,
+    pub params: Arc<ADSRParams>,
+}
+
+// what generate() needs from before a sample to check it
+#[derive(Clone, Copy)]
+pub(crate) struct Before {
+    phase: ADSRPhase,
+    event: ADSREvent,
+    value: f32,
+}
+
+impl ADSR {
+    // the first sample that broke an invariant since the voice was created or the violation
+    // was last taken, checked on every sample with the "debug-validate" feature
+    pub fn violation(&self) -> Option<&Violation> {
+        self.violation.as_ref()
+    }
+
+    // hand the violation over and look for the next one
+    pub fn take_violation(&mut self) -> Option<Violation> {
+        self.violation.take()
+    }
+
+    pub(crate) fn before_sample(&self) -> Before {
+        Before { phase: self.current_phase, event: self.current_event, value: self.current_val }
+    }
+
+    // check the sample value (raw, before generate() handles non-finite ones) generated since before
+    pub(crate) fn validate(&mut self, before: Before, value: f32) {
+        if self.violation.is_some() {
+            return;
+        }
+        let invariant = if !value.is_finite() {
+            Some(Invariant::NotFinite)
+        } else if !(0.0..=1.0).contains(&value) {
+            Some(Invariant::OutOfRange)
+        } else if self.attack_reversed(before, value) {
+            Some(Invariant::AttackReversed)
+        } else if !self.phase_follows(before) {
+            Some(Invariant::PhaseOrder)
+        } else {
+            None
+        };
+        if let Some(invariant) = invariant {
+            self.violation = Some(Violation {
+                clock: self.clock - 1,
+                invariant,
+                value,
+                previous: before.value,
+                phase: self.current_phase,
+                previous_phase: before.phase,
+                state: self.save_state(),
+                params: Arc::clone(&self.params),
+            });
+        }
+    }
+
+    // a step of the same attack away from the peak. glides, crossfades and smoothed parameters
+    // move the trajectory itself and are left out
+    fn attack_reversed(&self, before: Before, value: f32) -> bool {
+        if before.phase != ADSRPhase::Attack || self.current_phase != ADSRPhase::Attack
+            || before.event != self.current_event || self.note_on_duration <= TICKS
+            || self.bridge.is_some() || self.param_fade.is_some() || !self.slews.is_empty() {
+            return false;
+        }
+        let [_, attack, ..] = self.note_on_stages();
+        let rising = attack.target() >= attack.from();
+        if rising {
+            value < before.value - 1e-6
+        } else {
+            value > before.value + 1e-6
+        }
+    }
+
+    fn phase_follows(&self, before: Before) -> bool {
+        use ADSRPhase::*;
+        let (from, to) = (before.phase, self.current_phase);
+        let started = before.event == ADSREvent::NoteOff && self.current_event != ADSREvent::NoteOff;
+        let rank = |phase| [Delay, Attack, Hold, Decay, Sustain].iter().position(|&p| p == phase);
+        from == to
+            || started // a new note or legato picking its timeline up again
+            || matches!(to, Delay | Attack | Release | Silence | Shutdown)
+            || from == Silence
+            || (self.mode == EnvelopeMode::SustainLoop && matches!(to, Hold | Decay))
+            || matches!((rank(from), rank(to)), (Some(a), Some(b)) if b > a)
+    }
+}
+
+impl fmt::Display for Invariant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Invariant::NotFinite => "level not finite",
+            Invariant::OutOfRange => "level out of 0..=1",
+            Invariant::AttackReversed => "attack reversed",
+            Invariant::PhaseOrder => "phases out of order",
+        })
+    }
+}
+
+fn phase_name(phase: ADSRPhase) -> &'static str {
+    match phase {
+        ADSRPhase::Delay => "Delay",
+        ADSRPhase::Attack => "Attack",
+        ADSRPhase::Hold => "Hold",
+        ADSRPhase::Decay => "Decay",
+        ADSRPhase::Sustain => "Sustain",
+        ADSRPhase::Release => "Release",
+        ADSRPhase::Silence => "Silence",
+        ADSRPhase::Shutdown => "Shutdown",
+    }
+}
+
+fn event_name(event: ADSREvent) -> &'static str {
+    match event {
+        ADSREvent::NoteOn | ADSREvent::NoteOnWithVelocity(_) => "note on",
+        ADSREvent::NoteOff => "note off",
+        ADSREvent::Retrigger => "retrigger",
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = &self.state;
+        write!(
+            f,
+            "sample {}: {}, {} {} -> {} {}; {}, velocity {}, key {}, note on timeline {} samples, \
+             note off timeline {} samples, level at note off {}, pedal {}, attack origin {:?}, \
+             gliding {}, fading {}, crossfading parameters {}, {} events scheduled",
+            self.clock,
+            self.invariant,
+            phase_name(self.previous_phase),
+            self.previous,
+            phase_name(self.phase),
+            self.value,
+            event_name(s.current_event),
+            s.velocity,
+            s.key,
+            s.note_on_duration as f64 / TICKS as f64,
+            s.note_off_duration as f64 / TICKS as f64,
+            s.last_gate_val,
+            s.sustain_pedal,
+            s.attack_origin,
+            s.bridge.is_some(),
+            s.fade.is_some(),
+            s.param_fade.is_some(),
+            s.scheduled.len(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ADSRParamKind, CurveKind, RetriggerMode};
+    use alloc::string::ToString;
+
+    fn render(adsr: &mut ADSR, samples: usize) {
+        for n in 0..samples {
+            if n == 30 {
+                adsr.note_off();
+            }
+            adsr.generate();
+        }
+    }
+
+    #[test]
+    fn clean_notes() {
+        for mode in [EnvelopeMode::Gated, EnvelopeMode::Percussive, EnvelopeMode::SustainLoop, EnvelopeMode::ASR] {
+            let mut adsr = ADSR::new(0.005, 0.01, 0.5, 0.01, 1000.0);
+            adsr.set_param(ADSRParamKind::AttackCurve(-0.7));
+            adsr.set_param(ADSRParamKind::DecayCurve(0.4));
+            adsr.set_mode(mode);
+            adsr.set_retrigger_mode(RetriggerMode::FromCurrentValue);
+            adsr.note_on();
+            render(&mut adsr, 35);
+            adsr.note_on();
+            render(&mut adsr, 60);
+            adsr.kill(5);
+            render(&mut adsr, 10);
+            assert!(adsr.violation().is_none());
+        }
+    }
+
+    fn wobble(x: f32) -> f32 {
+        if x < 0.5 { x } else { x - 0.2 }
+    }
+
+    #[test]
+    fn peek_leaves_it() {
+        let mut adsr = ADSR::new(0.01, 0.01, 0.5, 0.01, 1000.0);
+        adsr.set_param(ADSRParamKind::AttackShape(CurveKind::Func(wobble)));
+        adsr.note_on();
+        render(&mut adsr, 5);
+        adsr.peek(); // the sample that reverses
+        assert!(adsr.violation().is_none());
+        adsr.generate();
+        assert_eq!(adsr.violation().unwrap().clock, 5);
+    }
+
+    #[test]
+    fn reports_the_first() {
+        let mut adsr = ADSR::new(0.01, 0.01, 0.5, 0.01, 1000.0);
+        adsr.set_param(ADSRParamKind::AttackShape(CurveKind::Func(wobble)));
+        adsr.note_on();
+        render(&mut adsr, 40);
+        let violation = adsr.take_violation().unwrap();
+        assert_eq!(violation.invariant, Invariant::AttackReversed);
+        assert_eq!(violation.clock, 5);
+        assert!(violation.to_string().starts_with("sample 5: attack reversed, Attack 0.4"));
+        assert!(violation.to_string().contains("-> Attack 0.3; note on, velocity 1, key 60"));
+
+        adsr.set_param(ADSRParamKind::AttackShape(CurveKind::Func(|x| 2.0 * x)));
+        adsr.note_on();
+        render(&mut adsr, 10);
+        assert_eq!(adsr.violation().unwrap().invariant, Invariant::OutOfRange);
+    }
+}